@@ -0,0 +1,87 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// The ways compiling patterns, walking directories and searching can fail.
+/// Each displays as the message the `grepr` command prints for it.
+#[derive(Debug)]
+pub enum GreprError {
+    /// A pattern neither regex engine accepts
+    InvalidPattern(String),
+    /// A pattern, or with `None` all of them joined, compiling to more than
+    /// `limit` bytes
+    PatternTooBig { pattern: Option<String>, limit: usize },
+    /// A matcher that could not be built for some reason other than its
+    /// patterns, such as running out of automaton states
+    Engine(String),
+    InvalidGlob(String),
+    InvalidTypeDefinition(String),
+    UnknownType(String),
+    /// A setting that is badly formed, out of range or at odds with another
+    InvalidArgument(String),
+    /// Something this build or the chosen engine cannot do
+    Unsupported(String),
+    /// Reading or writing failed; `path` is `None` for standard output and
+    /// readers handed in without a name
+    Io { path: Option<PathBuf>, source: io::Error },
+    IsADirectory(PathBuf),
+    /// The directory walker failed, for instance on a symlink cycle
+    #[cfg(not(target_arch = "wasm32"))]
+    Walk(ignore::Error),
+    /// A `--pre` command that could not be run or exited unsuccessfully
+    Preprocessor { command: String, reason: String },
+    /// The search was called off from outside before it finished
+    Cancelled,
+}
+
+impl GreprError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> GreprError {
+        GreprError::Io { path: Some(path.into()), source }
+    }
+}
+
+impl fmt::Display for GreprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GreprError::InvalidPattern(pattern) => write!(f, "Invalid pattern \"{}\"", pattern),
+            GreprError::PatternTooBig { pattern: Some(pattern), limit } => write!(
+                f,
+                "Pattern \"{}\" exceeds the regex size limit of {} bytes, see --regex-size-limit",
+                pattern, limit
+            ),
+            GreprError::PatternTooBig { pattern: None, limit } => write!(
+                f,
+                "The patterns together exceed the regex size limit of {} bytes, see --regex-size-limit",
+                limit
+            ),
+            GreprError::InvalidGlob(glob) => write!(f, "Invalid glob \"{}\"", glob),
+            GreprError::InvalidTypeDefinition(def) => write!(f, "Invalid type definition \"{}\"", def),
+            GreprError::UnknownType(name) => write!(f, "Unknown file type \"{}\"", name),
+            GreprError::Engine(message) | GreprError::InvalidArgument(message) | GreprError::Unsupported(message) => {
+                f.write_str(message)
+            }
+            GreprError::Io { path: Some(path), source } => write!(f, "{}: {}", path.display(), source),
+            GreprError::Io { path: None, source } => write!(f, "{}", source),
+            GreprError::IsADirectory(path) => write!(f, "{} is a directory", path.display()),
+            #[cfg(not(target_arch = "wasm32"))]
+            GreprError::Walk(e) => write!(f, "{}", e),
+            GreprError::Preprocessor { command, reason } => write!(f, "preprocessor \"{}\" failed: {}", command, reason),
+            GreprError::Cancelled => f.write_str("search cancelled"),
+        }
+    }
+}
+
+impl Error for GreprError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GreprError::Io { source, .. } => Some(source),
+            #[cfg(not(target_arch = "wasm32"))]
+            GreprError::Walk(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GreprError {
+    fn from(source: io::Error) -> GreprError {
+        GreprError::Io { path: None, source }
+    }
+}
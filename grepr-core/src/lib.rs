@@ -0,0 +1,22 @@
+//! The search engine behind the `grepr` command: compiling patterns, finding
+//! the files to search and picking out the lines that match, with no
+//! command-line parsing of its own.
+
+mod error;
+mod matcher;
+mod search;
+mod vfs;
+// Walking directories needs a real file system, which wasm32 lacks
+#[cfg(not(target_arch = "wasm32"))]
+mod walk;
+
+pub use error::GreprError;
+pub use matcher::{build_hyperscan, build_matcher, Matcher, PatternOptions};
+pub use search::{find_lines, search_lines, Line, Lines, Match, SearchOptions, Searcher};
+#[cfg(not(target_arch = "wasm32"))]
+pub use vfs::OsFs;
+pub use vfs::{FileSystem, MemoryFs};
+#[cfg(not(target_arch = "wasm32"))]
+pub use walk::{build_globset, build_overrides, build_types, find_files, walk_files, Directories, SortBy, WalkOptions};
+
+type MyResult<T> = Result<T, GreprError>;
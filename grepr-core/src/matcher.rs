@@ -0,0 +1,492 @@
+use crate::{GreprError, MyResult};
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use regex::bytes::{Regex, RegexBuilder};
+use std::ops::Range;
+
+// How the patterns themselves are interpreted
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PatternOptions {
+    pub fixed_strings: bool,
+    pub insensitive: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    pub pcre: bool,
+    pub multiline: bool,
+    pub captures: bool,
+    pub no_unicode: bool,
+    pub size_limit: Option<usize>,
+    pub dfa_size_limit: Option<usize>,
+}
+
+/// A compiled set of patterns, whichever engine runs them. Spans are the
+/// byte ranges of the matches in a line, in order and never overlapping.
+pub trait Matcher: std::fmt::Debug + Send + Sync {
+    fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>>;
+
+    fn is_match(&self, text: &[u8]) -> bool {
+        !self.find_spans(text).is_empty()
+    }
+
+    /// Fills in a `--replace` template from the captures of the match at
+    /// `span`. Engines without capture groups give the template back as is.
+    fn expand(&self, _text: &[u8], _span: &Range<usize>, template: &str) -> Vec<u8> {
+        template.as_bytes().to_vec()
+    }
+
+    /// Which engine this is, for diagnostics.
+    fn name(&self) -> &'static str;
+}
+
+impl Matcher for Regex {
+    fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>> {
+        self.find_iter(text).map(|m| m.range()).collect()
+    }
+
+    fn is_match(&self, text: &[u8]) -> bool {
+        Regex::is_match(self, text)
+    }
+
+    fn expand(&self, text: &[u8], span: &Range<usize>, template: &str) -> Vec<u8> {
+        let mut expanded = vec![];
+        match self
+            .captures_iter(text)
+            .find(|caps| caps.get(0).is_some_and(|m| m.range() == *span))
+        {
+            Some(caps) => caps.expand(template.as_bytes(), &mut expanded),
+            None => expanded.extend_from_slice(template.as_bytes()),
+        }
+        expanded
+    }
+
+    fn name(&self) -> &'static str {
+        "regex"
+    }
+}
+
+// Aho-Corasick keeps no capture groups, and --replace never picks it
+impl Matcher for AhoCorasick {
+    fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>> {
+        self.find_iter(text).map(|m| m.range()).collect()
+    }
+
+    fn is_match(&self, text: &[u8]) -> bool {
+        AhoCorasick::is_match(self, text)
+    }
+
+    fn name(&self) -> &'static str {
+        "literal"
+    }
+}
+
+// One regex per pattern, as joining them would renumber backreferences
+#[cfg(feature = "fancy-regex")]
+#[derive(Debug)]
+pub struct FancyMatcher(Vec<fancy_regex::Regex>);
+
+#[cfg(feature = "fancy-regex")]
+impl Matcher for FancyMatcher {
+    fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>> {
+        // fancy-regex only searches str, so lines that are not valid UTF-8
+        // never match
+        let text = match std::str::from_utf8(text) {
+            Ok(text) => text,
+            Err(_) => return vec![],
+        };
+        leftmost_longest(
+            self.0
+                .iter()
+                .flat_map(|re| re.find_iter(text).map_while(Result::ok))
+                .map(|m| m.range())
+                .collect(),
+        )
+    }
+
+    fn expand(&self, text: &[u8], span: &Range<usize>, template: &str) -> Vec<u8> {
+        let caps = std::str::from_utf8(text).ok().and_then(|text| {
+            self.0.iter().find_map(|re| {
+                re.captures_from_pos(text, span.start)
+                    .ok()
+                    .flatten()
+                    .filter(|caps| caps.get(0).is_some_and(|m| m.range() == *span))
+            })
+        });
+        match caps {
+            Some(caps) => {
+                let mut dst = String::new();
+                caps.expand(template, &mut dst);
+                dst.into_bytes()
+            }
+            None => template.as_bytes().to_vec(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "fancy-regex"
+    }
+}
+
+#[cfg(feature = "hyperscan")]
+pub struct HyperscanMatcher {
+    // Compiled for UTF-8 with Unicode classes, used on lines that decode,
+    // unless `--no-unicode` asked for bytes throughout
+    utf8: Option<Scanner>,
+    bytes: Scanner,
+}
+
+// Scratch space serves one scan at a time, so each scan takes its own from
+// a pool and puts it back when done rather than waiting on the others
+#[cfg(feature = "hyperscan")]
+struct Scanner {
+    database: hyperscan::BlockDatabase,
+    scratch: std::sync::Mutex<Vec<hyperscan::Scratch>>,
+}
+
+#[cfg(feature = "hyperscan")]
+impl Scanner {
+    fn new(database: hyperscan::BlockDatabase) -> MyResult<Self> {
+        let scratch = database.alloc_scratch().map_err(|e| GreprError::Engine(e.to_string()))?;
+        Ok(Scanner { database, scratch: std::sync::Mutex::new(vec![scratch]) })
+    }
+
+    fn scan(&self, text: &[u8]) -> Vec<Range<usize>> {
+        let pooled = self.scratch.lock().unwrap().pop();
+        let Some(scratch) = pooled.or_else(|| self.database.alloc_scratch().ok()) else {
+            return vec![];
+        };
+        let mut spans = vec![];
+        let _ = self.database.scan(text, &scratch, |_: u32, from: u64, to: u64, _: u32| {
+            spans.push(from as usize..to as usize);
+            hyperscan::Matching::Continue
+        });
+        self.scratch.lock().unwrap().push(scratch);
+        spans
+    }
+}
+
+#[cfg(feature = "hyperscan")]
+impl std::fmt::Debug for HyperscanMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HyperscanMatcher")
+    }
+}
+
+#[cfg(feature = "hyperscan")]
+impl Matcher for HyperscanMatcher {
+    fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>> {
+        // Scanning invalid UTF-8 in UTF-8 mode is undefined, so such lines
+        // fall back to the byte-wise database
+        let scanner = match &self.utf8 {
+            Some(utf8) if std::str::from_utf8(text).is_ok() => utf8,
+            _ => &self.bytes,
+        };
+        // Hyperscan reports every end offset of every pattern, so it needs
+        // the same overlap resolution as the per-pattern engines
+        leftmost_longest(scanner.scan(text))
+    }
+
+    fn name(&self) -> &'static str {
+        "hyperscan"
+    }
+}
+
+// Keep the leftmost-longest of any overlapping matches
+#[cfg(any(feature = "fancy-regex", feature = "hyperscan"))]
+fn leftmost_longest(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|span| (span.start, usize::MAX - span.end));
+    let mut end = 0;
+    spans.retain(|span| {
+        let keep = span.start >= end;
+        if keep {
+            end = span.end.max(span.start + 1);
+        }
+        keep
+    });
+    spans
+}
+
+pub fn build_matcher(patterns: &[String], options: &PatternOptions) -> MyResult<Box<dyn Matcher>> {
+    let PatternOptions {
+        fixed_strings,
+        insensitive,
+        word_regexp,
+        line_regexp,
+        pcre,
+        captures,
+        no_unicode,
+        ..
+    } = *options;
+    // An empty pattern list (e.g. `-f /dev/null`) matches nothing at all
+    if patterns.is_empty() {
+        return AhoCorasick::new(patterns)
+            .map(|ac| Box::new(ac) as Box<dyn Matcher>)
+            .map_err(|e| GreprError::Engine(e.to_string()));
+    }
+    // Aho-Corasick can only fold ASCII case and knows nothing of anchors or
+    // capture groups, so anything fancier goes through an escaped regex instead
+    if fixed_strings
+        && !captures
+        && !word_regexp
+        && !line_regexp
+        && (!insensitive || no_unicode || patterns.iter().all(|p| p.is_ascii()))
+    {
+        let ac = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(insensitive)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns)
+            .map_err(|e| GreprError::Engine(e.to_string()))?;
+        return Ok(Box::new(ac));
+    }
+    let mut sources = vec![];
+    let mut needs_fancy = pcre;
+    for pattern in patterns {
+        let source = if fixed_strings {
+            regex::escape(pattern)
+        } else {
+            pattern.to_string()
+        };
+        // Compile each pattern alone first so errors name the culprit, falling
+        // back to the backtracking engine for syntax the regex crate lacks
+        let compiled = regex_builder(&source, options).build();
+        if let Err(regex::Error::CompiledTooBig(limit)) = compiled {
+            return Err(GreprError::PatternTooBig {
+                pattern: Some(pattern.to_string()),
+                limit,
+            });
+        }
+        if !needs_fancy && compiled.is_err() {
+            #[cfg(feature = "fancy-regex")]
+            let supported = build_fancy(&source, insensitive, options.multiline).is_some();
+            #[cfg(not(feature = "fancy-regex"))]
+            let supported = false;
+            if !supported {
+                return Err(GreprError::InvalidPattern(pattern.to_string()));
+            }
+            needs_fancy = true;
+        }
+        sources.push(format!("(?:{})", source));
+    }
+    if needs_fancy && no_unicode {
+        return Err(GreprError::Unsupported(
+            "--no-unicode is not supported by the -P/--pcre2 engine".to_string(),
+        ));
+    }
+    if needs_fancy {
+        #[cfg(feature = "fancy-regex")]
+        return sources
+            .iter()
+            .zip(patterns)
+            .map(|(source, pattern)| {
+                let source = wrap_pattern(source, word_regexp, line_regexp);
+                build_fancy(&source, insensitive, options.multiline)
+                    .ok_or_else(|| GreprError::InvalidPattern(pattern.to_string()))
+            })
+            .collect::<MyResult<_>>()
+            .map(|regexes| Box::new(FancyMatcher(regexes)) as Box<dyn Matcher>);
+        #[cfg(not(feature = "fancy-regex"))]
+        return Err(GreprError::Unsupported("grepr was built without -P/--pcre2 support".to_string()));
+    }
+    match regex_builder(&wrap_pattern(&sources.join("|"), word_regexp, line_regexp), options).build() {
+        Err(regex::Error::CompiledTooBig(limit)) => Err(GreprError::PatternTooBig { pattern: None, limit }),
+        result => result
+            .map(|re| Box::new(re) as Box<dyn Matcher>)
+            .map_err(|e| GreprError::Engine(e.to_string())),
+    }
+}
+
+// The regex crate's own defaults apply to any limit left unset
+fn regex_builder(source: &str, options: &PatternOptions) -> RegexBuilder {
+    let mut builder = RegexBuilder::new(source);
+    builder
+        .case_insensitive(options.insensitive)
+        .multi_line(options.multiline)
+        .unicode(!options.no_unicode);
+    if let Some(limit) = options.size_limit {
+        builder.size_limit(limit);
+    }
+    if let Some(limit) = options.dfa_size_limit {
+        builder.dfa_size_limit(limit);
+    }
+    builder
+}
+
+#[cfg(feature = "fancy-regex")]
+fn build_fancy(
+    source: &str,
+    insensitive: bool,
+    multiline: bool,
+) -> Option<fancy_regex::Regex> {
+    let source = match (insensitive, multiline) {
+        (true, true) => format!("(?im){}", source),
+        (true, false) => format!("(?i){}", source),
+        (false, true) => format!("(?m){}", source),
+        (false, false) => source.to_string(),
+    };
+    fancy_regex::Regex::new(&source).ok()
+}
+
+// Hyperscan compiles every pattern into one automaton, which scales far
+// better than an alternation once there are hundreds of them
+#[cfg(feature = "hyperscan")]
+pub fn build_hyperscan(patterns: &[String], options: &PatternOptions) -> MyResult<Box<dyn Matcher>> {
+    use hyperscan::{Builder, Pattern, PatternFlags, Patterns};
+
+    let mut flags = PatternFlags::SOM_LEFTMOST | PatternFlags::ALLOWEMPTY;
+    if options.insensitive {
+        flags |= PatternFlags::CASELESS;
+    }
+    if options.multiline {
+        flags |= PatternFlags::MULTILINE;
+    }
+    let sources: Vec<String> = patterns
+        .iter()
+        .map(|pattern| {
+            let source = if options.fixed_strings {
+                regex::escape(pattern)
+            } else {
+                pattern.to_string()
+            };
+            wrap_pattern(&source, options.word_regexp, options.line_regexp)
+        })
+        .collect();
+    let compile = |flags: PatternFlags| -> MyResult<Scanner> {
+        let mut compiled = vec![];
+        for (pattern, source) in patterns.iter().zip(&sources) {
+            let invalid = || GreprError::InvalidPattern(pattern.to_string());
+            let single = Pattern::with_flags(source, flags).map_err(|_| invalid())?;
+            // Build each pattern alone first so errors name the culprit
+            single.build::<hyperscan::BlockMode>().map_err(|_| invalid())?;
+            compiled.push(single);
+        }
+        let database = Patterns(compiled).build().map_err(|e| GreprError::Engine(e.to_string()))?;
+        Scanner::new(database)
+    };
+    let utf8 = match options.no_unicode {
+        true => None,
+        false => Some(compile(flags | PatternFlags::UTF8 | PatternFlags::UCP)?),
+    };
+    Ok(Box::new(HyperscanMatcher { utf8, bytes: compile(flags)? }))
+}
+
+#[cfg(not(feature = "hyperscan"))]
+pub fn build_hyperscan(_: &[String], _: &PatternOptions) -> MyResult<Box<dyn Matcher>> {
+    Err(GreprError::Unsupported("grepr was built without hyperscan support".to_string()))
+}
+
+fn wrap_pattern(pattern: &str, word_regexp: bool, line_regexp: bool) -> String {
+    if line_regexp {
+        format!("^(?:{})$", pattern)
+    } else if word_regexp {
+        format!(r"\b(?:{})\b", pattern)
+    } else {
+        pattern.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_matcher, wrap_pattern, PatternOptions};
+    use crate::GreprError;
+    use regex::bytes::Regex;
+
+    #[test]
+    fn test_wrap_pattern() {
+        assert_eq!(wrap_pattern("a|b", false, false), "a|b");
+
+        // The whole alternation must be bounded, not just its ends
+        let re = Regex::new(&wrap_pattern("a|b", true, false)).unwrap();
+        assert!(re.is_match(b"x b y"));
+        assert!(!re.is_match(b"xb ay"));
+
+        // Line anchoring wins over word boundaries
+        let re = Regex::new(&wrap_pattern("a|b", true, true)).unwrap();
+        assert!(re.is_match(b"b"));
+        assert!(!re.is_match(b"a b"));
+    }
+
+    #[test]
+    fn test_build_matcher() {
+        // Metacharacters are literal in fixed-string mode
+        let patterns = |v: &[&str]| -> Vec<String> {
+            v.iter().map(|s| s.to_string()).collect()
+        };
+        let fixed = PatternOptions {
+            fixed_strings: true,
+            ..Default::default()
+        };
+        let matcher = build_matcher(&patterns(&["a.c"]), &fixed).unwrap();
+        assert_eq!(matcher.name(), "literal");
+        assert_eq!(matcher.find_spans(b"abc a.c"), vec![4..7]);
+
+        // Non-ASCII case folding falls back to an escaped regex
+        let options = PatternOptions {
+            insensitive: true,
+            ..fixed
+        };
+        let matcher = build_matcher(&patterns(&["É."]), &options).unwrap();
+        assert_eq!(matcher.name(), "regex");
+        assert_eq!(matcher.find_spans("xé.".as_bytes()), vec![1..4]);
+
+        assert!(matches!(
+            build_matcher(&patterns(&["*foo"]), &PatternOptions::default()),
+            Err(GreprError::InvalidPattern(pattern)) if pattern == "*foo"
+        ));
+        assert!(build_matcher(&patterns(&["*foo"]), &fixed).is_ok());
+
+        // Several patterns are ORed together in either mode
+        for options in [PatternOptions::default(), fixed] {
+            let matcher = build_matcher(&patterns(&["b", "d"]), &options).unwrap();
+            assert_eq!(matcher.find_spans(b"abcd"), vec![1..2, 3..4]);
+        }
+
+        // Line anchoring applies to the alternation as a whole
+        let options = PatternOptions {
+            line_regexp: true,
+            ..Default::default()
+        };
+        let matcher = build_matcher(&patterns(&["a", "b"]), &options).unwrap();
+        assert!(matcher.find_spans(b"ab").is_empty());
+        assert_eq!(matcher.find_spans(b"b"), vec![0..1]);
+
+        // Multiline anchors match at every line boundary
+        let options = PatternOptions {
+            multiline: true,
+            ..options
+        };
+        let matcher = build_matcher(&patterns(&["b"]), &options).unwrap();
+        assert_eq!(matcher.find_spans(b"ab\nb\n"), vec![3..4]);
+
+        // Without Unicode, classes are ASCII, . is any byte and only ASCII
+        // case folds
+        let options = PatternOptions {
+            insensitive: true,
+            no_unicode: true,
+            ..Default::default()
+        };
+        let matcher = build_matcher(&patterns(&[r"\w+"]), &options).unwrap();
+        assert_eq!(matcher.find_spans("aé".as_bytes()), vec![0..1]);
+        // The Kelvin sign only folds to k under Unicode rules
+        let matcher = build_matcher(&patterns(&["k"]), &options).unwrap();
+        assert_eq!(matcher.find_spans("K\u{212A}".as_bytes()), vec![0..1]);
+        let matcher = build_matcher(&patterns(&["a.b"]), &options).unwrap();
+        assert_eq!(matcher.find_spans(b"A\xFFb"), vec![0..3]);
+
+        // No patterns at all never matches
+        let matcher = build_matcher(&[], &PatternOptions::default()).unwrap();
+        assert!(matcher.find_spans(b"abc").is_empty());
+
+        // Look-around and backreferences fall back to the backtracking engine,
+        // with group numbers local to each pattern
+        #[cfg(feature = "fancy-regex")]
+        {
+            let matcher =
+                build_matcher(&patterns(&["x(?!y)", r"(\w)\1"]), &PatternOptions::default())
+                    .unwrap();
+            assert_eq!(matcher.name(), "fancy-regex");
+            assert_eq!(matcher.find_spans(b"xy xz aab"), vec![3..4, 6..8]);
+        }
+
+        // The offending pattern is named in the error
+        let err = build_matcher(&patterns(&["a", "(b"]), &PatternOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"(b\"");
+    }
+}
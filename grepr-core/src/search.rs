@@ -0,0 +1,646 @@
+use crate::{FileSystem, GreprError, Matcher, MyResult};
+use std::{
+    collections::VecDeque,
+    io::BufRead,
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub invert_match: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub max_matches: Option<usize>,
+    pub line_terminator: u8,
+    pub multiline: bool,
+    pub passthru: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            invert_match: false,
+            before_context: 0,
+            after_context: 0,
+            max_matches: None,
+            line_terminator: b'\n',
+            multiline: false,
+            passthru: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub number: usize,
+    pub offset: usize,
+    pub text: Vec<u8>,
+    pub is_match: bool,
+    pub spans: Vec<Range<usize>>,
+}
+
+impl Line {
+    // What --trim prints: the line without surrounding whitespace, its
+    // terminator kept and its spans moved along and clipped to what is left
+    pub fn trim(self, line_terminator: u8) -> Line {
+        let content = self.text.strip_suffix(&[line_terminator]).unwrap_or(&self.text);
+        let start = content
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(content.len());
+        let end = content
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(start, |i| i + 1);
+        let mut text = content[start..end].to_vec();
+        text.extend_from_slice(&self.text[content.len()..]);
+        let clip = |i: usize| i.clamp(start, end) - start;
+        Line {
+            offset: self.offset + start,
+            text,
+            spans: self.spans.iter().map(|span| clip(span.start)..clip(span.end)).collect(),
+            ..self
+        }
+    }
+}
+
+/// A selected line and where it was found. The line keeps its terminator,
+/// and the spans are byte ranges within it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Match {
+    /// `None` when the lines came from a reader with no path.
+    pub path: Option<PathBuf>,
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: Vec<u8>,
+    pub spans: Vec<Range<usize>>,
+}
+
+impl Match {
+    /// The match `line` stands for, found in `path`. A context line makes
+    /// one too, for sinks that report both alike.
+    pub fn new(line: Line, path: Option<&Path>) -> Match {
+        Match {
+            path: path.map(Path::to_path_buf),
+            line_number: line.number,
+            byte_offset: line.offset,
+            line: line.text,
+            spans: line.spans,
+        }
+    }
+}
+
+// A line's bytes, terminator included, and the spans matched within it
+type Record = (Vec<u8>, Vec<Range<usize>>);
+
+// Under -U the whole file is searched at once so matches can cross line
+// boundaries; each line then carries its piece of every match touching it
+fn multiline_records<T: BufRead>(
+    file: &mut T,
+    pattern: &dyn Matcher,
+    line_terminator: u8,
+) -> MyResult<VecDeque<Record>> {
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    let matches = pattern.find_spans(&buf);
+    let mut records = VecDeque::new();
+    let mut first = 0;
+    let mut start = 0;
+    while start < buf.len() {
+        let end = buf[start..]
+            .iter()
+            .position(|&b| b == line_terminator)
+            .map_or(buf.len(), |i| start + i + 1);
+        let content_end = if buf[end - 1] == line_terminator { end - 1 } else { end };
+        // Matches are in order, so those over before this line can be dropped
+        while matches
+            .get(first)
+            .is_some_and(|m| m.end < start || (m.end == start && !m.is_empty()))
+        {
+            first += 1;
+        }
+        let spans = matches[first..]
+            .iter()
+            .take_while(|m| m.start < end)
+            .filter(|m| m.end > start || m.start == start)
+            .map(|m| {
+                let piece_start = m.start.max(start).min(content_end);
+                piece_start - start..m.end.min(content_end).max(piece_start) - start
+            })
+            .collect();
+        records.push_back((buf[start..end].to_vec(), spans));
+        start = end;
+    }
+    Ok(records)
+}
+
+/// Searches readers with one compiled pattern and one set of options.
+#[derive(Debug, Clone, Copy)]
+pub struct Searcher<'a> {
+    pattern: &'a dyn Matcher,
+    options: SearchOptions,
+}
+
+impl<'a> Searcher<'a> {
+    pub fn new(pattern: &'a dyn Matcher, options: SearchOptions) -> Searcher<'a> {
+        Searcher { pattern, options }
+    }
+
+    /// The selected and context lines of `reader` in order, each read only
+    /// once the one before it has been taken. After an error nothing more
+    /// is read.
+    pub fn lines<R: BufRead>(&self, reader: R) -> Lines<'a, R> {
+        Lines {
+            file: reader,
+            pattern: self.pattern,
+            options: self.options,
+            records: None,
+            before: VecDeque::with_capacity(self.options.before_context),
+            pending: VecDeque::new(),
+            line_number: 0,
+            offset: 0,
+            after_remaining: 0,
+            match_count: 0,
+            done: false,
+        }
+    }
+
+    /// Only the selected lines of `reader`, leaving out any context, as
+    /// matches found in `path`.
+    pub fn search<R>(&self, reader: R, path: Option<&Path>) -> impl Iterator<Item = MyResult<Match>> + 'a
+    where
+        R: BufRead + 'a,
+    {
+        let path = path.map(Path::to_path_buf);
+        self.lines(reader).filter_map(move |line| match line {
+            Ok(line) if line.is_match => Some(Ok(Match::new(line, path.as_deref()))),
+            Ok(_) => None,
+            Err(GreprError::Io { path: None, source }) => Some(Err(GreprError::Io {
+                path: path.clone(),
+                source,
+            })),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Opens the file at `path` and searches it as [`Searcher::search`] does.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn search_path(&self, path: &Path) -> MyResult<impl Iterator<Item = MyResult<Match>> + 'a> {
+        let file = std::fs::File::open(path).map_err(|e| GreprError::io(path, e))?;
+        Ok(self.search(std::io::BufReader::new(file), Some(path)))
+    }
+
+    /// Searches every file at or under each of `paths` in `fs` as
+    /// [`Searcher::search`] does. A file that cannot be listed or opened
+    /// yields an error in its place and the search goes on.
+    pub fn search_fs(
+        &self,
+        fs: &'a dyn FileSystem,
+        paths: &'a [PathBuf],
+    ) -> impl Iterator<Item = MyResult<Match>> + 'a {
+        let searcher = *self;
+        paths
+            .iter()
+            .flat_map(move |path| fs.files(path))
+            .flat_map(move |file| -> Box<dyn Iterator<Item = MyResult<Match>> + 'a> {
+                match file.and_then(|file| Ok((fs.open(&file)?, file))) {
+                    Ok((reader, file)) => Box::new(searcher.search(reader, Some(&file))),
+                    Err(e) => Box::new(iter::once(Err(e))),
+                }
+            })
+    }
+}
+
+/// The iterator returned by [`Searcher::lines`].
+pub struct Lines<'a, R> {
+    file: R,
+    pattern: &'a dyn Matcher,
+    options: SearchOptions,
+    records: Option<VecDeque<Record>>,
+    before: VecDeque<Line>,
+    pending: VecDeque<Line>,
+    line_number: usize,
+    offset: usize,
+    after_remaining: usize,
+    match_count: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Lines<'_, R> {
+    /// How many bytes of the reader have been searched so far.
+    pub fn bytes_read(&self) -> usize {
+        self.offset
+    }
+
+    fn next_record(&mut self) -> MyResult<Option<Record>> {
+        let line_terminator = self.options.line_terminator;
+        if self.options.multiline {
+            if self.records.is_none() {
+                self.records = Some(multiline_records(&mut self.file, self.pattern, line_terminator)?);
+            }
+            return Ok(self.records.as_mut().and_then(VecDeque::pop_front));
+        }
+        let mut text = vec![];
+        if self.file.read_until(line_terminator, &mut text)? == 0 {
+            return Ok(None);
+        }
+        let content = text.strip_suffix(&[line_terminator]).unwrap_or(&text);
+        let spans = self.pattern.find_spans(content);
+        Ok(Some((text, spans)))
+    }
+
+    // Reads on until some line is known to be wanted, or there are no more
+    fn advance(&mut self) -> MyResult<()> {
+        let SearchOptions {
+            invert_match,
+            before_context,
+            after_context,
+            max_matches,
+            passthru,
+            ..
+        } = self.options;
+        while self.pending.is_empty() {
+            if max_matches == Some(self.match_count) && self.after_remaining == 0 && !passthru {
+                self.done = true;
+                return Ok(());
+            }
+            let Some((text, spans)) = self.next_record()? else {
+                self.done = true;
+                return Ok(());
+            };
+            let bytes_read = text.len();
+            self.line_number += 1;
+            let is_match = spans.is_empty() == invert_match;
+            let mut line = Line {
+                number: self.line_number,
+                offset: self.offset,
+                text,
+                is_match,
+                spans,
+            };
+            self.offset += bytes_read;
+            // Past the last allowed match, a matching line only shows as
+            // trailing context, the way GNU grep prints it
+            if is_match && max_matches == Some(self.match_count) {
+                if self.after_remaining == 0 {
+                    self.done = true;
+                    return Ok(());
+                }
+                line.is_match = false;
+                line.spans.clear();
+            }
+            if line.is_match {
+                self.pending.extend(self.before.drain(..));
+                self.pending.push_back(line);
+                self.after_remaining = after_context;
+                self.match_count += 1;
+            } else if passthru {
+                self.pending.push_back(line);
+            } else if self.after_remaining > 0 {
+                self.pending.push_back(line);
+                self.after_remaining -= 1;
+            } else if before_context > 0 {
+                if self.before.len() == before_context {
+                    self.before.pop_front();
+                }
+                self.before.push_back(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<'_, R> {
+    type Item = MyResult<Line>;
+
+    fn next(&mut self) -> Option<MyResult<Line>> {
+        if self.pending.is_empty() && !self.done {
+            if let Err(e) = self.advance() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// All the lines [`Searcher::lines`] gives, held in memory until the file
+/// has been read to the end. [`search_lines`] hands them over as they are
+/// found instead.
+pub fn find_lines<T: BufRead>(
+    file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+) -> MyResult<Vec<Line>> {
+    Searcher::new(pattern, *options).lines(file).collect()
+}
+
+// Hands each selected or context line to `emit` in order as soon as it is
+// known to be wanted, and returns how many bytes were read
+pub fn search_lines<T, F>(
+    file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    mut emit: F,
+) -> MyResult<usize>
+where
+    T: BufRead,
+    F: FnMut(Line) -> MyResult<()>,
+{
+    let mut lines = Searcher::new(pattern, *options).lines(file);
+    for line in lines.by_ref() {
+        emit(line?)?;
+    }
+    Ok(lines.bytes_read())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_lines, Line, Match, SearchOptions, Searcher};
+    use crate::Matcher;
+    use regex::bytes::{Regex, RegexBuilder};
+    use std::{io::Cursor, ops::Range, path::Path};
+
+    #[test]
+    fn test_find_lines() {
+        let text = b"Lorem\nIpsum\r\nDOLOR";
+
+        // The pattern _or_ should match the one line "Lorem"
+        let re1 = Regex::new("or").unwrap();
+        let matches = find_lines(Cursor::new(&text), &re1, &SearchOptions::default());
+        assert!(matches.is_ok());
+        assert_eq!(matches.unwrap(), vec![Line {
+            number: 1,
+            offset: 0,
+            text: b"Lorem\n".to_vec(),
+            is_match: true,
+            spans: vec![Range { start: 1, end: 3 }],
+        }]);
+
+        // When inverted, the function should match the other two lines
+        let options = SearchOptions {
+            invert_match: true,
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re1, &options);
+        assert!(matches.is_ok());
+        let lines: Vec<(usize, usize)> = matches
+            .unwrap()
+            .iter()
+            .map(|line| (line.number, line.offset))
+            .collect();
+        assert_eq!(lines, vec![(2, 6), (3, 13)]);
+
+        // This regex will be case-insensitive
+        let re2 = RegexBuilder::new("or")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        
+        // The two lines "Lorem" and "DOLOR" should match
+        let matches = find_lines(Cursor::new(&text), &re2, &SearchOptions::default());
+        assert!(matches.is_ok());
+        assert_eq!(matches.unwrap().len(), 2);
+
+        // When inverted, the one remaining line should match
+        let options = SearchOptions {
+            invert_match: true,
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re2, &options);
+        assert!(matches.is_ok());
+        assert_eq!(matches.unwrap().len(), 1);
+
+        // The end anchor should match before the newline
+        let re3 = Regex::new("^Lorem$").unwrap();
+        let matches = find_lines(Cursor::new(&text), &re3, &SearchOptions::default());
+        assert!(matches.is_ok());
+        assert_eq!(matches.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_find_lines_after_context() {
+        let text = b"a\nb\na\nc\nd\ne\n";
+        let re = Regex::new("a").unwrap();
+
+        // Overlapping context after adjacent matches must not repeat lines
+        let options = SearchOptions {
+            after_context: 2,
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, bool)> = matches
+            .iter()
+            .map(|line| (line.number, line.is_match))
+            .collect();
+        assert_eq!(lines, vec![
+            (1, true),
+            (2, false),
+            (3, true),
+            (4, false),
+            (5, false),
+        ]);
+    }
+
+    #[test]
+    fn test_find_lines_before_context() {
+        let text = b"a\nb\nc\nd\ne\nd\n";
+        let re = Regex::new("d").unwrap();
+
+        // Only the most recent lines are kept, and none are emitted twice
+        let options = SearchOptions {
+            before_context: 2,
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, bool)> = matches
+            .iter()
+            .map(|line| (line.number, line.is_match))
+            .collect();
+        assert_eq!(lines, vec![
+            (2, false),
+            (3, false),
+            (4, true),
+            (5, false),
+            (6, true),
+        ]);
+    }
+
+    #[test]
+    fn test_find_lines_null_data() {
+        let text = b"a\nb\0c\0";
+        let re = Regex::new("^[ac]$").unwrap();
+        let options = SearchOptions {
+            line_terminator: b'\0',
+            ..Default::default()
+        };
+
+        // Records split on NUL only, with the terminator kept but not matched
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, &[u8])> = matches
+            .iter()
+            .map(|line| (line.number, line.text.as_slice()))
+            .collect();
+        assert_eq!(lines, vec![(2, &b"c\0"[..])]);
+    }
+
+    #[test]
+    fn test_find_lines_multiline() {
+        let text = b"foo\nbar\nbaz\nfoo bar";
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+
+        // A match across lines selects each of them, split into pieces
+        let re = Regex::new("o\nb").unwrap();
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, usize, Vec<Range<usize>>)> = matches
+            .iter()
+            .map(|line| (line.number, line.offset, line.spans.clone()))
+            .collect();
+        assert_eq!(lines, vec![
+            (1, 0, vec![Range { start: 2, end: 3 }]),
+            (2, 4, vec![Range { start: 0, end: 1 }]),
+        ]);
+
+        // Anchors hold at each line, and inversion picks untouched lines
+        let re = RegexBuilder::new("^ba[rz]$").multi_line(true).build().unwrap();
+        let options = SearchOptions {
+            invert_match: true,
+            ..options
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let numbers: Vec<usize> = matches.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, vec![1, 4]);
+    }
+
+    #[test]
+    fn test_find_lines_max_matches() {
+        let text = b"a\nb\na\na\n";
+        let re = Regex::new("a").unwrap();
+
+        // Searching stops at the first match once the limit is reached
+        let options = SearchOptions {
+            max_matches: Some(1),
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].number, 1);
+
+        // Trailing context is still printed, later matches in it as context
+        let options = SearchOptions {
+            after_context: 2,
+            max_matches: Some(1),
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, bool)> = matches.iter().map(|line| (line.number, line.is_match)).collect();
+        assert_eq!(lines, vec![(1, true), (2, false), (3, false)]);
+    }
+
+    #[test]
+    fn test_find_lines_passthru() {
+        let text = b"a\nb\na\nc\n";
+        let re = Regex::new("a").unwrap();
+
+        // Every line comes through, with only the matches marked
+        let options = SearchOptions {
+            passthru: true,
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<(usize, bool)> = matches.iter().map(|line| (line.number, line.is_match)).collect();
+        assert_eq!(lines, vec![(1, true), (2, false), (3, true), (4, false)]);
+
+        // Past the last allowed match the rest of the input still passes
+        // through up to the next match
+        let options = SearchOptions {
+            passthru: true,
+            max_matches: Some(1),
+            ..Default::default()
+        };
+        let matches = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let lines: Vec<usize> = matches.iter().map(|line| line.number).collect();
+        assert_eq!(lines, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_searcher_is_lazy() {
+        let text = b"fox\nhen\nfox\n";
+        let re = Regex::new("fox").unwrap();
+        let mut lines = Searcher::new(&re, SearchOptions::default()).lines(Cursor::new(&text));
+
+        // Nothing past the first match has been read when it is handed out
+        assert_eq!(lines.next().unwrap().unwrap().number, 1);
+        assert_eq!(lines.bytes_read(), 4);
+        assert_eq!(lines.next().unwrap().unwrap().number, 3);
+        assert!(lines.next().is_none());
+        assert_eq!(lines.bytes_read(), text.len());
+    }
+
+    #[test]
+    fn test_searcher_search() {
+        let re = Regex::new("o").unwrap();
+        let options = SearchOptions {
+            after_context: 1,
+            ..Default::default()
+        };
+        let searcher = Searcher::new(&re, options);
+
+        // Context lines are left out and the path is carried along
+        let text = b"fox\nhen\ndog";
+        let matches: Vec<Match> = searcher
+            .search(Cursor::new(&text), Some(Path::new("farm.txt")))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(matches, vec![
+            Match {
+                path: Some(Path::new("farm.txt").to_path_buf()),
+                line_number: 1,
+                byte_offset: 0,
+                line: b"fox\n".to_vec(),
+                spans: vec![Range { start: 1, end: 2 }],
+            },
+            Match {
+                path: Some(Path::new("farm.txt").to_path_buf()),
+                line_number: 3,
+                byte_offset: 8,
+                line: b"dog".to_vec(),
+                spans: vec![Range { start: 1, end: 2 }],
+            },
+        ]);
+
+        let matches = searcher.search_path(Path::new("../tests/inputs/fox.txt")).unwrap();
+        assert_eq!(matches.count(), 1);
+        assert!(searcher.search_path(Path::new("no/such/file")).is_err());
+    }
+
+    #[test]
+    fn test_find_lines_custom_matcher() {
+        // Any engine will do, here one that matches each digit on its own
+        #[derive(Debug)]
+        struct Digits;
+
+        impl Matcher for Digits {
+            fn find_spans(&self, text: &[u8]) -> Vec<Range<usize>> {
+                (0..text.len()).filter(|&i| text[i].is_ascii_digit()).map(|i| i..i + 1).collect()
+            }
+
+            fn name(&self) -> &'static str {
+                "digits"
+            }
+        }
+
+        let matches = find_lines(Cursor::new(b"a1\nbc\n23\n"), &Digits, &SearchOptions::default()).unwrap();
+        let lines: Vec<(usize, Vec<Range<usize>>)> = matches.into_iter().map(|line| (line.number, line.spans)).collect();
+        assert_eq!(lines, vec![
+            (1, vec![Range { start: 1, end: 2 }]),
+            (3, vec![Range { start: 0, end: 1 }, Range { start: 1, end: 2 }]),
+        ]);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{GreprError, MyResult};
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Cursor},
+    path::{Path, PathBuf},
+};
+
+/// Where [`Searcher::search_fs`](crate::Searcher::search_fs) finds and reads
+/// files, so a search can run over something other than the disk, such as
+/// an archive unpacked in a browser where there is no file system at all.
+pub trait FileSystem {
+    /// Every file at or under `path`, in the order they are to be searched.
+    /// A path that cannot be listed comes back as a single error.
+    fn files(&self, path: &Path) -> Vec<MyResult<PathBuf>>;
+
+    fn open(&self, path: &Path) -> MyResult<Box<dyn BufRead + '_>>;
+}
+
+/// Files held in memory, each under the path it was inserted with. A
+/// directory is any path some file lies under.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds a file, replacing any already at `path`.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn files(&self, path: &Path) -> Vec<MyResult<PathBuf>> {
+        let files: Vec<_> = self
+            .files
+            .keys()
+            .filter(|file| file.starts_with(path))
+            .map(|file| Ok(file.clone()))
+            .collect();
+        if files.is_empty() {
+            return vec![Err(not_found(path))];
+        }
+        files
+    }
+
+    fn open(&self, path: &Path) -> MyResult<Box<dyn BufRead + '_>> {
+        match self.files.get(path) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.as_slice()))),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> GreprError {
+    GreprError::io(path, io::Error::from(io::ErrorKind::NotFound))
+}
+
+/// The real file system, walked as a recursive search with no other options
+/// walks it.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFs;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSystem for OsFs {
+    fn files(&self, path: &Path) -> Vec<MyResult<PathBuf>> {
+        let paths = [path.to_string_lossy().into_owned()];
+        crate::walk_files(&paths, crate::Directories::Recurse, &crate::WalkOptions::default())
+            .map(|file| file.map(PathBuf::from))
+            .collect()
+    }
+
+    fn open(&self, path: &Path) -> MyResult<Box<dyn BufRead + '_>> {
+        let file = std::fs::File::open(path).map_err(|e| GreprError::io(path, e))?;
+        Ok(Box::new(io::BufReader::new(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSystem, MemoryFs};
+    use crate::{SearchOptions, Searcher};
+    use regex::bytes::Regex;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_memory_fs() {
+        let mut fs = MemoryFs::new();
+        fs.insert("docs/a.txt", "one fox\ntwo\n");
+        fs.insert("docs/sub/b.txt", "fox three\n");
+        fs.insert("docsx.txt", "fox\n");
+        let files: Vec<_> = fs.files(Path::new("docs")).into_iter().map(Result::unwrap).collect();
+        assert_eq!(files, [PathBuf::from("docs/a.txt"), PathBuf::from("docs/sub/b.txt")]);
+        assert!(fs.files(Path::new("nowhere"))[0].is_err());
+        assert!(fs.open(Path::new("docs")).is_err());
+
+        let re = Regex::new("fox").unwrap();
+        let searcher = Searcher::new(&re, SearchOptions::default());
+        let paths = [PathBuf::from("docs"), PathBuf::from("missing")];
+        let found: Vec<_> = searcher.search_fs(&fs, &paths).collect();
+        assert_eq!(found.len(), 3);
+        let found: Vec<_> = found[..2].iter().map(|found| found.as_ref().unwrap()).collect();
+        assert_eq!(found[0].path.as_deref(), Some(Path::new("docs/a.txt")));
+        assert_eq!(found[0].line, b"one fox\n");
+        assert_eq!(found[1].path.as_deref(), Some(Path::new("docs/sub/b.txt")));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::{GreprError, MyResult};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    overrides::{Override, OverrideBuilder},
+    types::{Types, TypesBuilder},
+    DirEntry, WalkBuilder, WalkParallel, WalkState,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::SystemTime,
+};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Directories {
+    Read,
+    Skip,
+    Recurse,
+}
+
+// How a recursive search walks directories, and which files it looks at as
+// judged by their base names
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    pub include: Option<GlobSet>,
+    pub exclude: GlobSet,
+    pub exclude_dir: GlobSet,
+    pub globs: Option<Override>,
+    pub types: Option<Types>,
+    pub max_depth: Option<usize>,
+    pub max_filesize: Option<u64>,
+    pub follow_links: bool,
+    pub one_file_system: bool,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    pub no_ignore_vcs: bool,
+    pub no_ignore_parent: bool,
+    pub sort: Option<(SortBy, bool)>,
+    // Threads walking each directory at once; with fewer than two the walk
+    // runs on the thread taking its files
+    pub threads: usize,
+}
+
+// What --sort and --sortr order the files of a recursive search by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    Path,
+    Modified,
+    Created,
+    Size,
+}
+
+impl WalkOptions {
+    fn is_match(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        self.include.as_ref().is_none_or(|include| include.is_match(name))
+            && !self.exclude.is_match(name)
+    }
+
+    fn prunes(&self, dir: &Path) -> bool {
+        let prunes = dir.file_name().is_some_and(|name| self.exclude_dir.is_match(name));
+        if prunes {
+            info!("skipping {}: excluded by --exclude-dir", dir.display());
+        }
+        prunes
+    }
+}
+
+pub fn build_globset(globs: &[String]) -> MyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|_| GreprError::InvalidGlob(glob.to_string()))?);
+    }
+    builder.build().map_err(|e| GreprError::Engine(e.to_string()))
+}
+
+// -g and --iglob globs match paths relative to where the search started,
+// the same way as lines of a .gitignore with the sense reversed
+pub fn build_overrides(globs: &[String], iglobs: &[String]) -> MyResult<Option<Override>> {
+    if globs.is_empty() && iglobs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = OverrideBuilder::new(env::current_dir()?);
+    for glob in globs {
+        builder.add(glob).map_err(|_| GreprError::InvalidGlob(glob.to_string()))?;
+    }
+    builder.case_insensitive(true).map_err(GreprError::Walk)?;
+    for glob in iglobs {
+        builder.add(glob).map_err(|_| GreprError::InvalidGlob(glob.to_string()))?;
+    }
+    builder.build().map(Some).map_err(GreprError::Walk)
+}
+
+// The built-in type table plus any --type-add definitions, with the
+// requested selections applied
+pub fn build_types(
+    additions: &[String],
+    selected: &[String],
+    negated: &[String],
+) -> MyResult<TypesBuilder> {
+    let mut builder = TypesBuilder::new();
+    builder.add_defaults();
+    for def in additions {
+        let invalid = || GreprError::InvalidTypeDefinition(def.to_string());
+        let (name, globs) = def.split_once(':').ok_or_else(invalid)?;
+        if globs.starts_with("include:") {
+            builder.add_def(def).map_err(|_| invalid())?;
+        } else {
+            for glob in globs.split(',') {
+                builder.add(name, glob).map_err(|_| invalid())?;
+            }
+        }
+    }
+    let known = builder.definitions();
+    for name in selected.iter().chain(negated) {
+        if !known.iter().any(|def| def.name() == name) {
+            return Err(GreprError::UnknownType(name.to_string()));
+        }
+    }
+    for name in selected {
+        builder.select(name);
+    }
+    for name in negated {
+        builder.negate(name);
+    }
+    Ok(builder)
+}
+
+pub fn find_files(
+    paths: &[String],
+    directories: Directories,
+    walk: &WalkOptions,
+) -> Vec<MyResult<String>> {
+    walk_files(paths, directories, walk).collect()
+}
+
+/// The files `find_files` finds, each one as soon as the walk reaches it, so
+/// searching can begin before a large tree has been walked to the end. With
+/// `threads` above one, that many threads walk each tree at once and its files
+/// come out in the order they are found. Under `--sort` every path named is
+/// walked whole before its files come out.
+pub fn walk_files<'a>(
+    paths: &'a [String],
+    directories: Directories,
+    walk: &'a WalkOptions,
+) -> Box<dyn Iterator<Item = MyResult<String>> + Send + 'a> {
+    if directories == Directories::Recurse {
+        Box::new(paths.iter().flat_map(move |path| -> Box<dyn Iterator<Item = _> + Send + 'a> {
+            if path.as_str() == "-" {
+                return Box::new(std::iter::once(Ok("-".to_string())));
+            }
+            let files: Box<dyn Iterator<Item = _> + Send + 'a> = if walk.threads > 1 {
+                let walker = walk_builder(path, walk).threads(walk.threads).build_parallel();
+                Box::new(walk_parallel(walker, walk.clone()))
+            } else {
+                Box::new(walk_builder(path, walk).build().filter_map(move |entry| found(walk, entry)))
+            };
+            match walk.sort {
+                Some((sort_by, reverse)) => Box::new(sort_files(files.collect(), sort_by, reverse).into_iter()),
+                None => Box::new(files),
+            }
+        }))
+    } else {
+        Box::new(paths.iter().filter_map(move |path| if path.as_str() == "-" {
+            Some(Ok("-".to_string()))
+        } else {
+            match fs::metadata(path) {
+                Err(e) => Some(Err(GreprError::io(path, e))),
+                Ok(metadata) if metadata.is_dir() => match directories {
+                    Directories::Skip => {
+                        info!("skipping {}: is a directory", path);
+                        None
+                    }
+                    _ => Some(Err(GreprError::IsADirectory(path.into()))),
+                },
+                Ok(_) => Some(Ok(path.to_owned())),
+            }
+        }))
+    }
+}
+
+fn walk_builder(path: &str, walk: &WalkOptions) -> WalkBuilder {
+    // Excluded directories are pruned whole rather than walked, though one
+    // named on the command line is always searched, and a symlink cycle is
+    // reported instead of followed
+    let pruning = walk.clone();
+    let mut builder = WalkBuilder::new(path);
+    if !walk.no_ignore {
+        builder
+            .add_custom_ignore_filename(".rgignore")
+            .add_custom_ignore_filename(".greprignore");
+    }
+    builder
+        .standard_filters(false)
+        .git_ignore(!walk.no_ignore && !walk.no_ignore_vcs)
+        .git_global(!walk.no_ignore && !walk.no_ignore_vcs)
+        .git_exclude(!walk.no_ignore && !walk.no_ignore_vcs)
+        .ignore(!walk.no_ignore)
+        .parents(!walk.no_ignore_parent)
+        .hidden(!walk.hidden)
+        .max_depth(walk.max_depth)
+        .max_filesize(walk.max_filesize)
+        .types(walk.types.clone().unwrap_or_else(Types::empty))
+        .overrides(walk.globs.clone().unwrap_or_else(Override::empty))
+        .follow_links(walk.follow_links)
+        .same_file_system(walk.one_file_system)
+        .filter_entry(move |entry| {
+            entry.depth() == 0
+                || !entry.file_type().is_some_and(|t| t.is_dir())
+                || !pruning.prunes(entry.path())
+        });
+    builder
+}
+
+// How many files a parallel walk may find ahead of those taken from it
+const FOUND_AHEAD: usize = 1024;
+
+// The walk runs on threads of its own, each file going out as soon as one of
+// them finds it, and stops at the next file found once nothing takes them
+fn walk_parallel(walker: WalkParallel, walk: WalkOptions) -> mpsc::IntoIter<MyResult<String>> {
+    let (sender, receiver) = mpsc::sync_channel(FOUND_AHEAD);
+    thread::spawn(move || {
+        walker.run(|| {
+            let (sender, walk) = (sender.clone(), &walk);
+            Box::new(move |entry| match found(walk, entry).map(|file| sender.send(file)) {
+                Some(Err(_)) => WalkState::Quit,
+                _ => WalkState::Continue,
+            })
+        })
+    });
+    receiver.into_iter()
+}
+
+// The file a walk's entry names, if it is one to search. Unfollowed links
+// are only searched when named outright
+fn found(walk: &WalkOptions, entry: Result<DirEntry, ignore::Error>) -> Option<MyResult<String>> {
+    entry
+        .map_err(GreprError::Walk)
+        .map(|entry| {
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                None
+            } else if file_type.is_symlink() && entry.depth() > 0 {
+                info!("skipping {}: symbolic link not followed", entry.path().display());
+                None
+            } else if !walk.is_match(entry.path()) {
+                info!("skipping {}: excluded by --include or --exclude", entry.path().display());
+                None
+            } else {
+                Some(String::from(entry.path().to_string_lossy()))
+            }
+        })
+        .transpose()
+}
+
+// Files whose metadata cannot be read sort first, like walk errors, which
+// stay ahead of everything in either direction
+fn sort_files(
+    files: Vec<MyResult<String>>,
+    sort_by: SortBy,
+    reverse: bool,
+) -> Vec<MyResult<String>> {
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    enum Key {
+        Path(PathBuf),
+        Time(Option<SystemTime>),
+        Size(Option<u64>),
+    }
+    let key = |file: &MyResult<String>| {
+        let path = Path::new(file.as_ref().ok()?);
+        Some(match sort_by {
+            SortBy::Path => Key::Path(path.to_path_buf()),
+            SortBy::Modified => Key::Time(fs::metadata(path).and_then(|m| m.modified()).ok()),
+            SortBy::Created => Key::Time(fs::metadata(path).and_then(|m| m.created()).ok()),
+            SortBy::Size => Key::Size(fs::metadata(path).map(|m| m.len()).ok()),
+        })
+    };
+    let mut keyed: Vec<_> = files.into_iter().map(|file| (key(&file), file)).collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (a, b) => a.cmp(b),
+    });
+    keyed.into_iter().map(|(_, file)| file).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_globset, find_files, walk_files, Directories, WalkOptions};
+    use rand::{distributions::Alphanumeric, Rng};
+
+    #[test]
+    fn test_find_files() {
+        // Verify that the function treats dash as a file
+        let files = find_files(&["-".to_string()], Directories::Read, &WalkOptions::default());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_ref().unwrap(), "-");
+
+        // Verify that the function finds a file known to exist
+        let files = find_files(&["../tests/inputs/fox.txt".to_string()], Directories::Read, &WalkOptions::default());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_ref().unwrap(), "../tests/inputs/fox.txt");
+
+        // The function should reject a directory without the recursive option
+        let files = find_files(&["../tests/inputs".to_string()], Directories::Read, &WalkOptions::default());
+        assert_eq!(files.len(), 1);
+        if let Err(e) = &files[0] {
+            assert_eq!(e.to_string(), "../tests/inputs is a directory");
+        }
+
+        // Skipped directories vanish without an error
+        let files = find_files(
+            &["../tests/inputs".to_string(), "../tests/inputs/fox.txt".to_string()],
+            Directories::Skip,
+            &WalkOptions::default(),
+        );
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_ref().unwrap(), "../tests/inputs/fox.txt");
+
+        // Verify the function recurses to find four files in the directory
+        let res = find_files(&["../tests/inputs".to_string()], Directories::Recurse, &WalkOptions::default());
+        let mut files: Vec<String> = res.iter()
+            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files, vec![
+            "../tests/inputs/bustle.txt",
+            "../tests/inputs/empty.txt",
+            "../tests/inputs/fox.txt",
+            "../tests/inputs/nobody.txt",
+        ]);
+
+        // Verify the function finds the file and recurses to find four files in the directory
+        let res = find_files(&["../tests/cli.rs".to_string(), "../tests/inputs".to_string()], Directories::Recurse, &WalkOptions::default());
+        let mut files: Vec<String> = res.iter()
+            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 5);
+        assert_eq!(files, vec![
+            "../tests/cli.rs",
+            "../tests/inputs/bustle.txt",
+            "../tests/inputs/empty.txt",
+            "../tests/inputs/fox.txt",
+            "../tests/inputs/nobody.txt",
+        ]);
+
+        // Only files whose base names match an --include glob are searched
+        let walk = WalkOptions {
+            include: Some(build_globset(&["b*".to_string(), "e*".to_string()]).unwrap()),
+            ..Default::default()
+        };
+        let res = find_files(&["../tests/inputs".to_string()], Directories::Recurse, &walk);
+        let mut files: Vec<String> = res.iter()
+            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["../tests/inputs/bustle.txt", "../tests/inputs/empty.txt"]);
+
+        // --exclude wins over --include
+        let walk = WalkOptions {
+            include: Some(build_globset(&["*.txt".to_string()]).unwrap()),
+            exclude: build_globset(&["[befn]*".to_string()]).unwrap(),
+            ..Default::default()
+        };
+        let res = find_files(&["../tests/inputs".to_string()], Directories::Recurse, &walk);
+        assert!(res.is_empty());
+
+        // --exclude-dir prunes whole subtrees, but not a directory given as a path
+        let walk = WalkOptions {
+            exclude_dir: build_globset(&["[ep]*".to_string(), "inputs".to_string()]).unwrap(),
+            ..Default::default()
+        };
+        let res = find_files(&["../tests".to_string()], Directories::Recurse, &walk);
+        let files: Vec<String> = res.iter()
+            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
+            .collect();
+        assert_eq!(files, vec!["../tests/cli.rs"]);
+        let res = find_files(&["../tests/inputs".to_string()], Directories::Recurse, &walk);
+        assert_eq!(res.len(), 4);
+
+        // Generate a random string to represent a nonexistent file
+        let bad: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(7)
+            .map(char::from)
+            .collect();
+        
+        // Verify that the function returns the bad file as an error
+        let files = find_files(&[bad], Directories::Read, &WalkOptions::default());
+        assert_eq!(files.len(), 1);
+        assert!(files[0].is_err());
+    }
+
+    #[test]
+    fn test_walk_files() {
+        // Files come out one at a time, in the order find_files lists them
+        let paths = ["../tests/inputs".to_string(), "-".to_string()];
+        let walk = WalkOptions::default();
+        let mut files = walk_files(&paths, Directories::Recurse, &walk);
+        let first = files.next().unwrap().unwrap();
+        let rest: Vec<_> = files.map(Result::unwrap).collect();
+        let all: Vec<_> = find_files(&paths, Directories::Recurse, &walk)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(all[0], first);
+        assert_eq!(all[1..], rest);
+        assert_eq!(rest.last().unwrap(), "-");
+
+        // Several threads find the same files, the paths named still in turn
+        let parallel = WalkOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        let mut found: Vec<_> = walk_files(&paths, Directories::Recurse, &parallel)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(found.pop().unwrap(), "-");
+        let mut expected = all[..all.len() - 1].to_vec();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+}
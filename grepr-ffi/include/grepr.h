@@ -0,0 +1,67 @@
+/*
+ * C bindings for the grepr search engine. Link against libgrepr_ffi, built
+ * by `cargo build -p grepr-ffi --release`.
+ */
+
+#ifndef GREPR_H
+#define GREPR_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* What grepr_search returns, as the grepr command exits */
+#define GREPR_MATCH 0
+#define GREPR_NO_MATCH 1
+#define GREPR_ERROR 2
+
+typedef struct grepr_options {
+    bool fixed_strings;
+    bool case_insensitive;
+    bool word_regexp;
+    bool line_regexp;
+    bool invert_match;
+    /* Search the files under a directory instead of rejecting it */
+    bool recursive;
+    /* Stop reading a file after this many selected lines; 0 for no limit */
+    size_t max_count;
+} grepr_options;
+
+/* A selected line, valid only until the callback returns. `line` is not
+ * NUL-terminated and keeps its line terminator. */
+typedef struct grepr_match {
+    const char *path;
+    size_t line_number;
+    size_t byte_offset;
+    const uint8_t *line;
+    size_t line_len;
+} grepr_match;
+
+/* Called with each selected line; return nonzero to stop the search */
+typedef int (*grepr_callback)(const grepr_match *match, void *user_data);
+
+/*
+ * Searches the file or directory at `path` for lines matching `pattern`,
+ * both NUL-terminated UTF-8. `options` may be NULL for the defaults.
+ * Returns GREPR_ERROR if the pattern is invalid or any file could not be
+ * read, which grepr_last_error then describes.
+ */
+int grepr_search(const char *pattern,
+                 const char *path,
+                 const grepr_options *options,
+                 grepr_callback callback,
+                 void *user_data);
+
+/* The message for the last error grepr_search met on this thread, or NULL.
+ * Valid until the next call to grepr_search on the same thread. */
+const char *grepr_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GREPR_H */
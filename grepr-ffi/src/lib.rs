@@ -0,0 +1,217 @@
+//! C bindings for the grepr search engine, declared in `include/grepr.h`, so
+//! editors and scripting languages can search without running the `grepr`
+//! command and parsing its output.
+
+use grepr_core::{build_matcher, walk_files, Directories, PatternOptions, SearchOptions, Searcher, WalkOptions};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    path::Path,
+    ptr,
+};
+
+pub const GREPR_MATCH: c_int = 0;
+pub const GREPR_NO_MATCH: c_int = 1;
+pub const GREPR_ERROR: c_int = 2;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GreprOptions {
+    pub fixed_strings: bool,
+    pub case_insensitive: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    pub invert_match: bool,
+    pub recursive: bool,
+    /// Zero for no limit
+    pub max_count: usize,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct GreprMatch {
+    pub path: *const c_char,
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: *const u8,
+    pub line_len: usize,
+}
+
+pub type GreprCallback = Option<unsafe extern "C" fn(*const GreprMatch, *mut c_void) -> c_int>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // A message cannot hold a NUL from a path, so any is cut off there
+    let message = message.split('\0').next().unwrap_or_default().to_string();
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+}
+
+/// Searches the file or directory at `path` for lines matching `pattern`,
+/// calling `callback` with each selected line until it returns nonzero.
+///
+/// # Safety
+///
+/// `pattern` and `path` must be NUL-terminated strings and `options` either
+/// null or a valid `grepr_options`. `user_data` is only passed through.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_search(
+    pattern: *const c_char,
+    path: *const c_char,
+    options: *const GreprOptions,
+    callback: GreprCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    let (Some(callback), false, false) = (callback, pattern.is_null(), path.is_null()) else {
+        set_last_error("grepr_search needs a pattern, a path and a callback".to_string());
+        return GREPR_ERROR;
+    };
+    let (Ok(pattern), Ok(path)) = (CStr::from_ptr(pattern).to_str(), CStr::from_ptr(path).to_str()) else {
+        set_last_error("The pattern and path must be UTF-8".to_string());
+        return GREPR_ERROR;
+    };
+    let options = options.as_ref().copied().unwrap_or_default();
+    let pattern_options = PatternOptions {
+        fixed_strings: options.fixed_strings,
+        insensitive: options.case_insensitive,
+        word_regexp: options.word_regexp,
+        line_regexp: options.line_regexp,
+        ..Default::default()
+    };
+    let matcher = match build_matcher(&[pattern.to_string()], &pattern_options) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return GREPR_ERROR;
+        }
+    };
+    let searcher = Searcher::new(
+        &*matcher,
+        SearchOptions {
+            invert_match: options.invert_match,
+            max_matches: (options.max_count > 0).then_some(options.max_count),
+            ..Default::default()
+        },
+    );
+    let directories = if options.recursive {
+        Directories::Recurse
+    } else {
+        Directories::Read
+    };
+    let mut status = GREPR_NO_MATCH;
+    let mut failed = false;
+    let paths = [path.to_string()];
+    let walk = WalkOptions::default();
+    'files: for file in walk_files(&paths, directories, &walk) {
+        let found = file.and_then(|file| Ok((searcher.search_path(Path::new(&file))?, file)));
+        let (found, file) = match found {
+            Ok(found) => found,
+            Err(e) => {
+                failed = true;
+                set_last_error(e.to_string());
+                continue;
+            }
+        };
+        let c_path = CString::new(file).unwrap_or_default();
+        for found in found {
+            let found = match found {
+                Ok(found) => found,
+                Err(e) => {
+                    failed = true;
+                    set_last_error(e.to_string());
+                    break;
+                }
+            };
+            status = GREPR_MATCH;
+            let found = GreprMatch {
+                path: c_path.as_ptr(),
+                line_number: found.line_number,
+                byte_offset: found.byte_offset,
+                line: found.line.as_ptr(),
+                line_len: found.line.len(),
+            };
+            if callback(&found, user_data) != 0 {
+                break 'files;
+            }
+        }
+    }
+    if failed {
+        GREPR_ERROR
+    } else {
+        status
+    }
+}
+
+/// The message for the last error `grepr_search` met on this thread, or
+/// null if it met none.
+#[no_mangle]
+pub extern "C" fn grepr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn collect(found: *const GreprMatch, user_data: *mut c_void) -> c_int {
+        let found = &*found;
+        let lines = &mut *(user_data as *mut Vec<(String, usize)>);
+        let path = CStr::from_ptr(found.path).to_string_lossy().into_owned();
+        lines.push((path, found.line_number));
+        c_int::from(lines.len() == 2)
+    }
+
+    #[test]
+    fn test_grepr_search() {
+        let mut lines: Vec<(String, usize)> = vec![];
+        let user_data = &mut lines as *mut _ as *mut c_void;
+        let status = unsafe {
+            grepr_search(
+                c"the".as_ptr(),
+                c"../tests/inputs/bustle.txt".as_ptr(),
+                ptr::null(),
+                Some(collect),
+                user_data,
+            )
+        };
+        assert_eq!(status, GREPR_MATCH);
+        assert_eq!(lines, [("../tests/inputs/bustle.txt".to_string(), 6)]);
+        assert!(grepr_last_error().is_null());
+
+        // A nonzero return from the callback stops the search
+        let options = GreprOptions {
+            case_insensitive: true,
+            recursive: true,
+            ..Default::default()
+        };
+        lines.clear();
+        let user_data = &mut lines as *mut _ as *mut c_void;
+        let status = unsafe {
+            grepr_search(
+                c"the".as_ptr(),
+                c"../tests/inputs".as_ptr(),
+                &options,
+                Some(collect),
+                user_data,
+            )
+        };
+        assert_eq!(status, GREPR_MATCH);
+        assert_eq!(lines.len(), 2);
+
+        let status = unsafe {
+            grepr_search(
+                c"*foo".as_ptr(),
+                c"../tests/inputs".as_ptr(),
+                ptr::null(),
+                Some(collect),
+                user_data,
+            )
+        };
+        assert_eq!(status, GREPR_ERROR);
+        let error = unsafe { CStr::from_ptr(grepr_last_error()) };
+        assert_eq!(error.to_str(), Ok("Invalid pattern \"*foo\""));
+    }
+}
@@ -0,0 +1,490 @@
+// The command line as clap sees it. `get_args` turns what is parsed here into
+// a Config, compiling patterns and reading pattern and file lists; values a
+// flag cannot hold at all are turned away while parsing.
+
+use crate::{bench::parse_iterations, parse_context_length, parse_count, parse_regex_limit, parse_size, parse_sort_by, BinaryFiles};
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    ArgAction, ArgGroup, Parser, Subcommand,
+};
+use clap_complete::Shell;
+use grepr_core::SortBy;
+use std::path::PathBuf;
+
+const SORT_KEYS: [&str; 4] = ["path", "modified", "created", "size"];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "grepr",
+    version = "0.1.0",
+    author = "Ken C.Y. Leung <kenleung5e28@gmail.com>",
+    about = "Rust grep",
+    after_help = "Options in the GREPR_OPTS environment variable apply before those given here.",
+    // -h is --no-filename, as in grep
+    disable_help_flag = true,
+    // and `grepr help` searches for "help"
+    disable_help_subcommand = true,
+    // A flag given again replaces its earlier value, so the command line
+    // can override GREPR_OPTS
+    args_override_self = true,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true,
+    group(ArgGroup::new("counting").args(["count", "count_matches"]).multiple(true)),
+    group(ArgGroup::new("listing").args(["files_with_matches", "files_without_match"])),
+    group(ArgGroup::new("engines").args(["engine", "pcre"])),
+)]
+pub(crate) struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print help
+    #[arg(long, action = ArgAction::Help)]
+    help: Option<bool>,
+
+    /// Skip files that an index built by `grepr index build` rules out
+    #[arg(long)]
+    pub use_index: bool,
+
+    #[command(flatten)]
+    pub index_dir: IndexDir,
+
+    /// Search pattern
+    #[arg(
+        value_name = "PATTERN",
+        required_unless_present_any = ["regexp", "pattern_file", "type_list", "list_candidates", "generate_completions", "generate_man"]
+    )]
+    pub pattern: Option<String>,
+
+    /// Input file(s) [default: -]
+    #[arg(value_name = "FILE")]
+    pub files: Vec<String>,
+
+    /// Print NUM lines of trailing context
+    #[arg(short = 'A', long, value_name = "NUM", value_parser = parse_context_length)]
+    pub after_context: Option<usize>,
+
+    /// Print NUM lines of leading context
+    #[arg(short = 'B', long, value_name = "NUM", value_parser = parse_context_length)]
+    pub before_context: Option<usize>,
+
+    /// How to handle files containing NUL bytes
+    #[arg(long, value_name = "TYPE", overrides_with_all = ["text", "skip_binary"])]
+    pub binary_files: Option<BinaryFiles>,
+
+    /// Print the byte offset of each output line
+    #[arg(short = 'b', long)]
+    pub byte_offset: bool,
+
+    /// Highlight matches
+    #[arg(
+        long,
+        alias = "colour",
+        value_name = "WHEN",
+        value_parser = ["auto", "always", "never"],
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    pub color: Option<String>,
+
+    /// Override output colors, e.g. ms=01;32:fn=34
+    #[arg(long, value_name = "SPEC")]
+    pub colors: Vec<String>,
+
+    /// Print the column of the first match on each line
+    #[arg(long)]
+    pub column: bool,
+
+    /// Print NUM lines of output context
+    #[arg(short = 'C', long, value_name = "NUM", value_parser = parse_context_length)]
+    pub context: Option<usize>,
+
+    /// Count matching lines
+    #[arg(short = 'c', long)]
+    pub count: bool,
+
+    /// Count every match rather than matching lines
+    #[arg(long)]
+    pub count_matches: bool,
+
+    /// Treat the pattern as a literal string
+    #[arg(short = 'F', long)]
+    pub fixed_strings: bool,
+
+    /// Search pattern, may be given more than once
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    pub regexp: Vec<String>,
+
+    /// Read patterns from FILE, one per line
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    pub pattern_file: Vec<String>,
+
+    /// How to handle directories
+    #[arg(short = 'd', long, value_name = "ACTION", value_parser = ["read", "skip", "recurse"])]
+    pub directories: Option<String>,
+
+    /// Skip files whose base name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Skip directories whose base name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    pub exclude_dir: Vec<String>,
+
+    /// Skip files matching any glob in FILE, one per line
+    #[arg(long, value_name = "FILE")]
+    pub exclude_from: Vec<String>,
+
+    /// Print the files that would be searched, without searching
+    #[arg(long = "files")]
+    pub list_candidates: bool,
+
+    /// Print only names of files with matches
+    #[arg(short = 'l', long)]
+    pub files_with_matches: bool,
+
+    /// Print only names of files without matches
+    #[arg(short = 'L', long)]
+    pub files_without_match: bool,
+
+    /// Search binary files as if they were text
+    #[arg(short = 'a', long, overrides_with_all = ["skip_binary", "binary_files"])]
+    pub text: bool,
+
+    /// Skip binary files (default with --recursive)
+    #[arg(short = 'I', overrides_with_all = ["text", "binary_files"])]
+    pub skip_binary: bool,
+
+    /// Search only files of TYPE, e.g. rust or py
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    pub types: Vec<String>,
+
+    /// Skip files of TYPE
+    #[arg(long, value_name = "TYPE")]
+    pub type_not: Vec<String>,
+
+    /// Define a file type, e.g. 'web:*.html,*.css'; those in GREPR_OPTS
+    /// apply to every search alongside any given here
+    #[arg(long, value_name = "TYPE:GLOBS")]
+    pub type_add: Vec<String>,
+
+    /// Strip whitespace from both ends of each printed line
+    #[arg(long)]
+    pub trim: bool,
+
+    /// Print every known file type and exit
+    #[arg(long)]
+    pub type_list: bool,
+
+    /// Search only files whose path matches GLOB, or skip them with !GLOB
+    #[arg(short = 'g', long, value_name = "GLOB")]
+    pub glob: Vec<String>,
+
+    /// Like --glob, but ignoring case
+    #[arg(long, value_name = "GLOB")]
+    pub iglob: Vec<String>,
+
+    /// Search the files listed in LIST, one per line, or in stdin for -
+    #[arg(long, value_name = "LIST")]
+    pub files_from: Vec<String>,
+
+    /// Like --files-from, but with the paths separated by NUL
+    #[arg(long, value_name = "LIST")]
+    pub files0_from: Vec<String>,
+
+    /// Print each file name once above its lines (default on a terminal)
+    #[arg(long, overrides_with = "no_heading")]
+    pub heading: bool,
+
+    /// Print the file name on every line
+    #[arg(long, overrides_with = "heading")]
+    pub no_heading: bool,
+
+    /// Print the file name for each match
+    #[arg(short = 'H', long, overrides_with = "no_filename")]
+    pub with_filename: bool,
+
+    /// Never print file names
+    #[arg(short = 'h', long, overrides_with = "with_filename")]
+    pub no_filename: bool,
+
+    /// Print SEP between context groups [default: --]
+    #[arg(long, value_name = "SEP", overrides_with = "no_group_separator")]
+    pub group_separator: Option<String>,
+
+    /// Print nothing between context groups
+    #[arg(long, overrides_with = "group_separator")]
+    pub no_group_separator: bool,
+
+    /// Link file names to FORMAT, such as file://{host}{path} or vscode, when
+    /// coloring; 'none' turns links off
+    #[arg(long, value_name = "FORMAT")]
+    pub hyperlink_format: Option<String>,
+
+    /// Align line content on a tab stop after the prefix
+    #[arg(short = 'T', long)]
+    pub initial_tab: bool,
+
+    /// Search hidden files and directories when recursing
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Search only files whose base name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Case-insensitive
+    #[arg(short = 'i', long)]
+    pub insensitive: bool,
+
+    /// Case-insensitive unless the pattern has an uppercase letter
+    #[arg(short = 'S', long)]
+    pub smart_case: bool,
+
+    /// Invert match
+    #[arg(short = 'v', long)]
+    pub invert_match: bool,
+
+    /// Print results as JSON Lines
+    #[arg(long)]
+    pub json: bool,
+
+    /// Use LABEL as the file name for standard input
+    #[arg(long, value_name = "LABEL")]
+    pub label: Option<String>,
+
+    /// Flush output after every line
+    #[arg(long)]
+    pub line_buffered: bool,
+
+    /// Print line numbers
+    #[arg(short = 'n', long)]
+    pub line_number: bool,
+
+    /// Descend at most NUM directories below each path
+    #[arg(long, value_name = "NUM", value_parser = parse_count("max depth"))]
+    pub max_depth: Option<usize>,
+
+    /// Skip files larger than SIZE when recursing, e.g. 10M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_filesize: Option<u64>,
+
+    /// Omit lines longer than NUM bytes
+    #[arg(short = 'M', long, value_name = "NUM", value_parser = parse_count("max columns"))]
+    pub max_columns: Option<usize>,
+
+    /// Print the first --max-columns bytes of long lines instead of omitting them
+    #[arg(long)]
+    pub max_columns_preview: bool,
+
+    /// Stop searching a file after NUM matching lines
+    #[arg(short = 'm', long, value_name = "NUM", value_parser = parse_count("max count"))]
+    pub max_count: Option<usize>,
+
+    /// Suppress error messages about unreadable files
+    #[arg(short = 's', long)]
+    pub no_messages: bool,
+
+    /// Don't respect .gitignore, .ignore or .greprignore files
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Don't respect ignore files in parent directories
+    #[arg(long)]
+    pub no_ignore_parent: bool,
+
+    /// Don't respect .gitignore and other git exclusions
+    #[arg(long)]
+    pub no_ignore_vcs: bool,
+
+    /// Search files through memory maps whenever possible
+    #[arg(long, overrides_with = "no_mmap")]
+    pub mmap: bool,
+
+    /// Never search files through memory maps
+    #[arg(long, overrides_with = "mmap")]
+    pub no_mmap: bool,
+
+    /// Let matches span lines
+    #[arg(short = 'U', long)]
+    pub multiline: bool,
+
+    /// Match bytes and ASCII classes only, folding only ASCII case
+    #[arg(long)]
+    pub no_unicode: bool,
+
+    /// Terminate file names with a NUL byte
+    #[arg(short = 'Z', long)]
+    pub null: bool,
+
+    /// Input and output records are terminated by NUL bytes
+    #[arg(short = 'z', long)]
+    pub null_data: bool,
+
+    /// Do not descend into directories on other file systems
+    #[arg(long)]
+    pub one_file_system: bool,
+
+    /// Print only the matched parts of lines
+    #[arg(short = 'o', long, conflicts_with = "counting")]
+    pub only_matching: bool,
+
+    /// Print every match as file:line:column:text
+    #[arg(long)]
+    pub vimgrep: bool,
+
+    /// Match only whole words
+    #[arg(short = 'w', long)]
+    pub word_regexp: bool,
+
+    /// Match only whole lines
+    #[arg(short = 'x', long)]
+    pub line_regexp: bool,
+
+    /// Print matches rewritten by TEMPLATE, e.g. '$1' or '${name}'
+    #[arg(long, value_name = "TEMPLATE")]
+    pub replace: Option<String>,
+
+    /// Transcode files from ENCODING to UTF-8 before searching, 'auto' to
+    /// follow byte order marks, 'none' to ignore them
+    #[arg(short = 'E', long, value_name = "ENCODING")]
+    pub encoding: Option<String>,
+
+    /// Allow each compiled regex up to SIZE bytes, with an optional K, M or G suffix
+    #[arg(long, value_name = "SIZE", value_parser = |val: &str| parse_regex_limit(val, "regex size limit"))]
+    pub regex_size_limit: Option<usize>,
+
+    /// Allow the regex engine's lazy DFA cache up to SIZE bytes
+    #[arg(long, value_name = "SIZE", value_parser = |val: &str| parse_regex_limit(val, "DFA size limit"))]
+    pub dfa_size_limit: Option<usize>,
+
+    /// Regex engine to search with
+    #[arg(long, value_name = "ENGINE", value_parser = ["default", "hyperscan"])]
+    pub engine: Option<String>,
+
+    /// Print every line, matching or not
+    #[arg(long, alias = "passthrough")]
+    pub passthru: bool,
+
+    /// Use the backtracking engine (look-around, backreferences)
+    #[arg(short = 'P', long = "pcre2")]
+    pub pcre: bool,
+
+    /// Suppress output, exit as soon as a match is found
+    #[arg(short = 'q', long, alias = "silent")]
+    pub quiet: bool,
+
+    /// Search the output of COMMAND run on each file instead of the file
+    #[arg(long, value_name = "COMMAND")]
+    pub pre: Option<String>,
+
+    /// Only run the --pre command on files whose base name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    pub pre_glob: Vec<String>,
+
+    /// Search the contents of gzip, bzip2, xz and zstd files
+    #[arg(long)]
+    pub search_zip: bool,
+
+    /// Search files in ascending order of SORTBY
+    #[arg(
+        long,
+        value_name = "SORTBY",
+        value_parser = PossibleValuesParser::new(SORT_KEYS).map(|key| parse_sort_by(&key)),
+        overrides_with = "sortr"
+    )]
+    pub sort: Option<SortBy>,
+
+    /// Search files in descending order of SORTBY
+    #[arg(
+        long,
+        value_name = "SORTBY",
+        value_parser = PossibleValuesParser::new(SORT_KEYS).map(|key| parse_sort_by(&key)),
+        overrides_with = "sort"
+    )]
+    pub sortr: Option<SortBy>,
+
+    /// Search NUM files at once [default: CPU count]
+    #[arg(short = 'j', long, value_name = "NUM", value_parser = parse_count("thread count"))]
+    pub threads: Option<usize>,
+
+    /// Print a summary of the search at the end
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Log which files are skipped and why to standard error
+    #[arg(long)]
+    pub verbose: bool,
+
+    /// Run the search N times, printing nothing it finds, and report how
+    /// long each run took walking, reading and matching; with several
+    /// threads the phases add up the time of all of them
+    #[arg(long, value_name = "N", value_parser = parse_iterations)]
+    pub bench: Option<usize>,
+
+    /// Log as --verbose does, plus how each file was searched and how long
+    /// it took
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Recursive search
+    #[arg(short = 'r', long)]
+    pub recursive: bool,
+
+    /// Recursive search, following all symbolic links
+    #[arg(short = 'R', long)]
+    pub dereference_recursive: bool,
+
+    /// Follow symbolic links while recursing
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Print a completion script for SHELL and exit
+    #[arg(long, value_name = "SHELL", hide = true)]
+    pub generate_completions: Option<Shell>,
+
+    /// Print a roff man page and exit
+    #[arg(long, hide = true, conflicts_with = "generate_completions")]
+    pub generate_man: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Manage the trigram index consulted by --use-index
+    #[command(subcommand_required = true, arg_required_else_help = true)]
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum IndexCommand {
+    /// Index every file under DIR, replacing any earlier index of it
+    Build {
+        /// Directory to index
+        #[arg(value_name = "DIR")]
+        dir: String,
+
+        #[command(flatten)]
+        index_dir: IndexDir,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct IndexDir {
+    /// Keep indexes in DIR [default: $XDG_CACHE_HOME/grepr]
+    #[arg(long = "index-dir", value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+    }
+}
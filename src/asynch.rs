@@ -0,0 +1,139 @@
+//! Searching from async code. The walk and the matching run on tokio's
+//! blocking threads and files are read through its async readers, so a search
+//! never holds up the threads the runtime drives its tasks on.
+
+use crate::{search::search_files, sink::FnSink, Config, MyResult, SearchStats};
+use grepr_core::Match;
+use std::{
+    cell::Cell,
+    io::{self, BufRead},
+    ops::ControlFlow,
+    panic,
+    sync::Arc,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, BufReader},
+    runtime::Handle,
+    sync::mpsc,
+};
+use tokio_util::io::SyncIoBridge;
+
+// How many selected lines the search may find before `on_match` has been
+// called with them
+const MATCHES_AHEAD: usize = 64;
+
+/// Searches as [`crate::search_with`] does, on a thread of tokio's blocking
+/// pool. Files are read through `tokio::fs`, standard input through
+/// `tokio::io::stdin`, and `on_match` is called on the awaiting task with
+/// each selected line. Binary files are treated as `--binary-files` says, and
+/// `--mmap` is ignored.
+///
+/// A search dropped before it is done goes on until it next selects a line; a
+/// cancellation token in the config stops it sooner.
+pub async fn search<F>(config: Arc<Config>, mut on_match: F) -> MyResult<SearchStats>
+where
+    F: FnMut(Match) -> ControlFlow<()>,
+{
+    let (found, mut receiver) = mpsc::channel(MATCHES_AHEAD);
+    let searching = tokio::task::spawn_blocking(move || {
+        let stopped = Cell::new(false);
+        let mut sink = FnSink {
+            // Sending fails once the receiver is gone, when `on_match` stopped
+            // the search or it was dropped
+            on_match: |line| match found.blocking_send(line) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            },
+            stopped: &stopped,
+        };
+        search_files(&config, &mut sink, &stopped, open)
+    });
+    while let Some(line) = receiver.recv().await {
+        if on_match(line).is_break() {
+            break;
+        }
+    }
+    drop(receiver);
+    match searching.await {
+        Ok(stats) => stats,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// Called on the blocking thread, which then waits on the runtime for each read
+fn open(filename: &str, _mmap: Option<bool>) -> io::Result<Box<dyn BufRead>> {
+    let reader: Box<dyn AsyncBufRead + Send + Unpin> = match filename {
+        "-" => Box::new(BufReader::new(tokio::io::stdin())),
+        _ => Box::new(BufReader::new(Handle::current().block_on(File::open(filename))?)),
+    };
+    Ok(Box::new(SyncIoBridge::new(reader)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search;
+    use crate::{BinaryFiles, Config};
+    use std::{env, fs, ops::ControlFlow, sync::Arc};
+
+    #[tokio::test]
+    async fn test_search() {
+        let config = Config::builder()
+            .pattern("The")
+            .file("tests/inputs/bustle.txt")
+            .file("tests/inputs/fox.txt")
+            .file("no/such/file")
+            .build()
+            .unwrap();
+        let config = Arc::new(config);
+        let mut found = vec![];
+        let stats = search(config.clone(), |line| {
+            found.push(line.line_number);
+            ControlFlow::Continue(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(found, [1, 2, 6, 1]);
+        assert_eq!((stats.searches, stats.searches_with_match, stats.errors), (2, 2, 1));
+
+        let mut found = vec![];
+        search(config.clone(), |line| {
+            found.push(line.line_number);
+            ControlFlow::Break(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(found, [1]);
+
+        // A search can be spawned onto a multithreaded runtime
+        fn assert_send<T: Send>(_: T) {}
+        assert_send(search(config, |_| ControlFlow::Continue(())));
+    }
+
+    #[tokio::test]
+    async fn test_binary_files() {
+        let path = env::temp_dir().join(format!("grepr-test-binary-files-{}", std::process::id()));
+        fs::write(&path, b"The fox\0\nThe dog\n").unwrap();
+        for (binary_files, lines, with_match) in [
+            (BinaryFiles::Binary, vec![], 1),
+            (BinaryFiles::WithoutMatch, vec![], 0),
+            (BinaryFiles::Text, vec![1, 2], 1),
+        ] {
+            let mut config = Config::builder()
+                .pattern("The")
+                .file(path.to_str().unwrap())
+                .build()
+                .unwrap();
+            config.binary_files = binary_files;
+            let mut found = vec![];
+            let stats = search(Arc::new(config), |line| {
+                found.push(line.line_number);
+                ControlFlow::Continue(())
+            })
+            .await
+            .unwrap();
+            assert_eq!((found, stats.searches_with_match), (lines, with_match));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
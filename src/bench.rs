@@ -0,0 +1,205 @@
+// --bench: the same search run over and over with its output thrown away,
+// timing each run as a whole and the parts it spends walking directories,
+// reading files and matching what was read
+
+use crate::{search::search_to, Config, GreprError, MyResult, SearchStats};
+use std::{
+    cell::Cell,
+    io::{self, BufRead, Read, Write},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// The phases are summed over every thread searching, so with several they
+// can add up to more than the run took
+#[derive(Debug)]
+pub(crate) struct Bench {
+    iterations: usize,
+    walk: Phase,
+    read: Phase,
+    matching: Phase,
+}
+
+// Nanoseconds spent in one phase of the current run
+#[derive(Debug, Default)]
+struct Phase(AtomicU64);
+
+impl Phase {
+    fn add(&self, spent: Duration) {
+        self.0.fetch_add(spent.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> Duration {
+        Duration::from_nanos(self.0.swap(0, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    wall: Duration,
+    bytes: usize,
+    walk: Duration,
+    read: Duration,
+    matching: Duration,
+}
+
+impl Timing {
+    fn add(&mut self, other: &Timing) {
+        self.wall += other.wall;
+        self.bytes += other.bytes;
+        self.walk += other.walk;
+        self.read += other.read;
+        self.matching += other.matching;
+    }
+
+    // The average run of the `runs` this is the total of
+    fn mean(&self, runs: usize) -> Timing {
+        let divisor = runs as u32;
+        Timing {
+            wall: self.wall / divisor,
+            bytes: self.bytes / runs,
+            walk: self.walk / divisor,
+            read: self.read / divisor,
+            matching: self.matching / divisor,
+        }
+    }
+
+    fn report(&self, out: &mut dyn Write, label: &str) -> io::Result<()> {
+        let ms = |spent: Duration| spent.as_secs_f64() * 1000.0;
+        writeln!(
+            out,
+            "{}: {:.3} ms, {:.1} MB/s (walk {:.3} ms, read {:.3} ms, match {:.3} ms)",
+            label,
+            ms(self.wall),
+            self.bytes as f64 / self.wall.as_secs_f64().max(f64::EPSILON) / 1e6,
+            ms(self.walk),
+            ms(self.read),
+            ms(self.matching),
+        )
+    }
+}
+
+impl Bench {
+    pub(crate) fn new(iterations: usize) -> Bench {
+        Bench {
+            iterations,
+            walk: Phase::default(),
+            read: Phase::default(),
+            matching: Phase::default(),
+        }
+    }
+
+    // Reports each run and then their mean, returning the totals of the
+    // first, which later runs only repeat
+    pub(crate) fn run(&self, config: &Config, out: &mut dyn Write) -> MyResult<SearchStats> {
+        let mut total = Timing::default();
+        let mut first = None;
+        for iteration in 1..=self.iterations {
+            let stats = search_to(config, &mut io::sink())?;
+            let timing = Timing {
+                wall: stats.elapsed,
+                bytes: stats.bytes_searched,
+                walk: self.walk.take(),
+                read: self.read.take(),
+                matching: self.matching.take(),
+            };
+            timing.report(out, &format!("iteration {}", iteration))?;
+            total.add(&timing);
+            first.get_or_insert(stats);
+        }
+        total.mean(self.iterations).report(out, &format!("mean of {}", self.iterations))?;
+        Ok(first.unwrap_or_default())
+    }
+
+    // Each file the walk finds, timed as it is looked for
+    pub(crate) fn timed_walk<'a, I>(&'a self, mut files: I) -> impl Iterator<Item = I::Item> + Send + 'a
+    where
+        I: Iterator + Send + 'a,
+    {
+        std::iter::from_fn(move || {
+            let started = Instant::now();
+            let file = files.next();
+            self.walk.add(started.elapsed());
+            file
+        })
+    }
+
+    pub(crate) fn add_read(&self, spent: Duration) {
+        self.read.add(spent);
+    }
+
+    // A file's search took `spent`, of which `read` went on reading it
+    pub(crate) fn add_search(&self, spent: Duration, read: Duration) {
+        self.read.add(read);
+        self.matching.add(spent.saturating_sub(read));
+    }
+}
+
+pub(crate) fn parse_iterations(val: &str) -> MyResult<usize> {
+    match val.parse() {
+        Ok(iterations) if iterations > 0 => Ok(iterations),
+        _ => Err(GreprError::InvalidArgument(format!(
+            "Invalid iteration count \"{}\"",
+            val
+        ))),
+    }
+}
+
+// Adds up the time spent in calls on `inner`, so reading a file can be told
+// apart from matching it
+pub(crate) struct Timed<'c, R> {
+    pub(crate) inner: R,
+    pub(crate) spent: &'c Cell<Duration>,
+}
+
+impl<R: Read> Read for Timed<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let read = self.inner.read(buf);
+        self.spent.set(self.spent.get() + started.elapsed());
+        read
+    }
+}
+
+impl<R: BufRead> BufRead for Timed<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let started = Instant::now();
+        let buf = self.inner.fill_buf();
+        self.spent.set(self.spent.get() + started.elapsed());
+        buf
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_iterations, Timing};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_iterations() {
+        assert_eq!(parse_iterations("1").unwrap(), 1);
+        assert_eq!(parse_iterations("25").unwrap(), 25);
+        for val in ["0", "-1", "two", ""] {
+            assert_eq!(
+                parse_iterations(val).unwrap_err().to_string(),
+                format!("Invalid iteration count \"{}\"", val)
+            );
+        }
+    }
+
+    #[test]
+    fn test_timing_mean() {
+        let ms = Duration::from_millis;
+        let mut total = Timing::default();
+        for (wall, bytes) in [(ms(10), 100), (ms(20), 300), (ms(60), 200)] {
+            total.add(&Timing { wall, bytes, walk: ms(3), read: wall / 2, matching: ms(1) });
+        }
+        let mean = total.mean(3);
+        assert_eq!((mean.wall, mean.bytes), (ms(30), 200));
+        assert_eq!((mean.walk, mean.read, mean.matching), (ms(3), ms(15), ms(1)));
+    }
+}
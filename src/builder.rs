@@ -0,0 +1,338 @@
+// Building a Config without going through the command line, for programs
+// that embed grepr as a library
+
+use crate::{progress::ProgressHook, BinaryFiles, CancellationToken, Colors, Config, GreprError, MyResult, Progress};
+use grepr_core::{build_matcher, Directories, PatternOptions, WalkOptions};
+use std::{sync::Arc, thread};
+
+/// Collects the settings of a search, checking them once in `build()`.
+/// Anything not set behaves as it would with the matching flag left off the
+/// command line, except that output is never colored or grouped under
+/// headings, whatever standard output is.
+///
+/// ```no_run
+/// let config = grepr::Config::builder()
+///     .pattern("fox")
+///     .file("tests/inputs")
+///     .recursive(true)
+///     .line_number(true)
+///     .build()
+///     .unwrap();
+/// grepr::run(config).unwrap();
+/// ```
+///
+/// With the `serde` feature a builder, and through it a `Config`, can be read
+/// from a job description such as `{"patterns": ["fox"], "recursive": true}`,
+/// any field left out taking its default.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ConfigBuilder {
+    patterns: Vec<String>,
+    files: Vec<String>,
+    recursive: bool,
+    fixed_strings: bool,
+    insensitive: bool,
+    word_regexp: bool,
+    line_regexp: bool,
+    invert_match: bool,
+    count: bool,
+    line_number: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    only_matching: bool,
+    quiet: bool,
+    max_count: Option<usize>,
+    before_context: usize,
+    after_context: usize,
+    with_filename: Option<bool>,
+    threads: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cancellation: Option<CancellationToken>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    progress: Option<ProgressHook>,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+impl ConfigBuilder {
+    /// Adds a pattern; a line is selected if any of them matches.
+    pub fn pattern(&mut self, pattern: &str) -> &mut ConfigBuilder {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    /// Adds a file or, with `recursive`, a directory to search. With none,
+    /// standard input is searched.
+    pub fn file(&mut self, file: &str) -> &mut ConfigBuilder {
+        self.files.push(file.to_string());
+        self
+    }
+
+    pub fn recursive(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.recursive = yes;
+        self
+    }
+
+    pub fn fixed_strings(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.fixed_strings = yes;
+        self
+    }
+
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.insensitive = yes;
+        self
+    }
+
+    pub fn word_regexp(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.word_regexp = yes;
+        self
+    }
+
+    pub fn line_regexp(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.line_regexp = yes;
+        self
+    }
+
+    pub fn invert_match(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.invert_match = yes;
+        self
+    }
+
+    pub fn count(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.count = yes;
+        self
+    }
+
+    pub fn line_number(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.line_number = yes;
+        self
+    }
+
+    pub fn files_with_matches(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.files_with_matches = yes;
+        self
+    }
+
+    pub fn files_without_match(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.files_without_match = yes;
+        self
+    }
+
+    pub fn only_matching(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.only_matching = yes;
+        self
+    }
+
+    pub fn quiet(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.quiet = yes;
+        self
+    }
+
+    /// Stops reading a file after `max` selected lines.
+    pub fn max_count(&mut self, max: usize) -> &mut ConfigBuilder {
+        self.max_count = Some(max);
+        self
+    }
+
+    /// Sets the lines of leading and trailing context, as `-B` and `-A` do.
+    pub fn context(&mut self, before: usize, after: usize) -> &mut ConfigBuilder {
+        self.before_context = before;
+        self.after_context = after;
+        self
+    }
+
+    /// Whether to prefix lines with the file name; by default only a search
+    /// of more than one file does.
+    pub fn with_filename(&mut self, yes: bool) -> &mut ConfigBuilder {
+        self.with_filename = Some(yes);
+        self
+    }
+
+    /// How many files to search at once, zero meaning one per core.
+    pub fn threads(&mut self, threads: usize) -> &mut ConfigBuilder {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Lets the search be stopped through `token`, which is usually a clone
+    /// of one the caller keeps.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut ConfigBuilder {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Reports to `progress` as the search goes; keep a clone of the `Arc`
+    /// to read back whatever it collects.
+    pub fn progress(&mut self, progress: Arc<dyn Progress>) -> &mut ConfigBuilder {
+        self.progress = Some(ProgressHook(progress));
+        self
+    }
+
+    /// Checks the settings and compiles the patterns, failing with the same
+    /// messages the command line gives for the same mistakes.
+    pub fn build(&self) -> MyResult<Config> {
+        if self.patterns.is_empty() {
+            return Err(GreprError::InvalidArgument("No pattern given".to_string()));
+        }
+        if self.files_with_matches && self.files_without_match {
+            return Err(GreprError::InvalidArgument(
+                "files_with_matches and files_without_match cannot both be set".to_string(),
+            ));
+        }
+        let pattern_options = PatternOptions {
+            fixed_strings: self.fixed_strings,
+            insensitive: self.insensitive,
+            word_regexp: self.word_regexp,
+            line_regexp: self.line_regexp,
+            ..Default::default()
+        };
+        let threads = self
+            .threads
+            .filter(|&threads| threads > 0)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        let directories = if self.recursive {
+            Directories::Recurse
+        } else {
+            Directories::Read
+        };
+        Ok(Config {
+            pattern: build_matcher(&self.patterns, &pattern_options)?,
+            files: if self.files.is_empty() {
+                vec!["-".to_string()]
+            } else {
+                self.files.clone()
+            },
+            directories,
+            walk_options: WalkOptions {
+                threads,
+                ..WalkOptions::default()
+            },
+            count: self.count,
+            count_matches: false,
+            invert_match: self.invert_match,
+            line_number: self.line_number,
+            after_context: self.after_context,
+            before_context: self.before_context,
+            color: false,
+            colors: Colors::default(),
+            files_with_matches: self.files_with_matches,
+            files_without_match: self.files_without_match,
+            only_matching: self.only_matching,
+            max_count: self.max_count,
+            quiet: self.quiet,
+            byte_offset: false,
+            with_filename: self.with_filename,
+            label: None,
+            null: false,
+            null_data: false,
+            binary_files: if self.recursive {
+                BinaryFiles::WithoutMatch
+            } else {
+                BinaryFiles::Binary
+            },
+            no_messages: false,
+            group_separator: Some("--".to_string()),
+            initial_tab: false,
+            line_buffered: false,
+            type_list: None,
+            list_candidates: false,
+            multiline: false,
+            replace: None,
+            vimgrep: false,
+            column: false,
+            heading: false,
+            json: false,
+            stats: false,
+            threads,
+            mmap: None,
+            encoding: None,
+            sniff_bom: true,
+            search_zip: false,
+            pre: None,
+            pre_glob: None,
+            passthru: false,
+            trim: false,
+            hyperlink_format: None,
+            max_columns: None,
+            max_columns_preview: false,
+            build_index: None,
+            generate: None,
+            cancellation: self.cancellation.clone(),
+            progress: self.progress.clone(),
+            index_query: None,
+            log_level: None,
+            bench: None,
+        })
+    }
+}
+
+impl TryFrom<ConfigBuilder> for Config {
+    type Error = GreprError;
+
+    fn try_from(builder: ConfigBuilder) -> MyResult<Config> {
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigBuilder;
+    use crate::{BinaryFiles, Config};
+    use grepr_core::Directories;
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::builder()
+            .pattern("fox")
+            .file("tests/inputs")
+            .recursive(true)
+            .count(true)
+            .build()
+            .unwrap();
+        assert_eq!(config.files, vec!["tests/inputs"]);
+        assert_eq!(config.directories, Directories::Recurse);
+        assert_eq!(config.binary_files, BinaryFiles::WithoutMatch);
+        assert!(config.count);
+        assert_eq!(config.pattern.find_spans(b"a fox"), vec![2..5]);
+
+        // With no files, standard input is searched
+        let config = Config::builder().pattern("fox").build().unwrap();
+        assert_eq!(config.files, vec!["-"]);
+
+        assert_eq!(ConfigBuilder::default().build().unwrap_err().to_string(), "No pattern given");
+        assert_eq!(
+            Config::builder().pattern("*foo").build().unwrap_err().to_string(),
+            "Invalid pattern \"*foo\""
+        );
+        assert!(Config::builder()
+            .pattern("fox")
+            .files_with_matches(true)
+            .files_without_match(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_deserialize_config() {
+        let config: Config =
+            serde_json::from_str(r#"{"patterns": ["fox"], "files": ["tests/inputs"], "recursive": true}"#).unwrap();
+        assert_eq!(config.files, vec!["tests/inputs"]);
+        assert_eq!(config.directories, Directories::Recurse);
+        assert!(!config.count);
+
+        let builder: ConfigBuilder = serde_json::from_str(r#"{"patterns": ["*foo"]}"#).unwrap();
+        assert!(Config::try_from(builder.clone()).is_err());
+        let round_trip: ConfigBuilder = serde_json::from_str(&serde_json::to_string(&builder).unwrap()).unwrap();
+        assert_eq!(round_trip.patterns, ["*foo"]);
+        assert!(serde_json::from_str::<Config>(r#"{"paterns": ["fox"]}"#).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// Stopping a search from outside it, such as from a GUI's stop button while
+// a big tree is still being searched on another thread
+
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A handle for stopping a search that was configured with
+/// [`ConfigBuilder::cancellation_token`](crate::ConfigBuilder::cancellation_token).
+/// Clones share one flag, so one can be kept to cancel with while another
+/// goes into the search.
+///
+/// The search checks before each file and each time it reads more of one,
+/// and then fails with [`GreprError::Cancelled`](crate::GreprError::Cancelled).
+/// Whatever was found before that has already been printed or passed on.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(flag: Arc<AtomicBool>) -> CancellationToken {
+        CancellationToken(flag)
+    }
+}
+
+// Reads through to `inner` until the token is cancelled, then fails, so a
+// search stops partway through a large file. Not with `Interrupted`, which
+// readers retry on
+pub(crate) struct Cancellable<'t, R> {
+    pub(crate) inner: R,
+    pub(crate) token: &'t CancellationToken,
+}
+
+impl<R> Cancellable<'_, R> {
+    fn check(&self) -> io::Result<()> {
+        match self.token.is_cancelled() {
+            true => Err(io::Error::other("search cancelled")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Cancellable<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.check()?;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancellationToken;
+    use crate::{search_with, Config, GreprError};
+    use std::{
+        ops::ControlFlow,
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let config = Config::builder()
+            .pattern("The")
+            .file("tests/inputs/bustle.txt")
+            .file("tests/inputs/fox.txt")
+            .cancellation_token(token.clone())
+            .build()
+            .unwrap();
+
+        // Cancelled partway through the first file, the search goes no further
+        let mut found = vec![];
+        let result = search_with(&config, |line| {
+            found.push(line.line_number);
+            token.cancel();
+            ControlFlow::Continue(())
+        });
+        assert!(matches!(result, Err(GreprError::Cancelled)));
+        assert_eq!(found, [1]);
+
+        let flag = Arc::new(AtomicBool::new(true));
+        let config = Config::builder()
+            .pattern("fox")
+            .file("tests/inputs/fox.txt")
+            .cancellation_token(CancellationToken::from(flag))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            search_with(&config, |_| ControlFlow::Continue(())),
+            Err(GreprError::Cancelled)
+        ));
+    }
+}
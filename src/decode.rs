@@ -0,0 +1,269 @@
+// What a file's bytes go through before they are searched. Each format is a
+// Decoder in REGISTRY, picked by the file's extension or the bytes it starts
+// with; a file no decoder claims is searched as it is. A new format is one
+// more Decoder and one more entry.
+
+use crate::{Config, MyResult};
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{BufRead, BufReader};
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    // Undone by --search-zip
+    Compressed,
+    // Text in something other than UTF-8, transcoded unless --encoding says
+    // otherwise
+    Encoded,
+}
+
+pub(crate) trait Decoder: Sync {
+    // What the format is called in messages
+    fn name(&self) -> &'static str;
+
+    fn kind(&self) -> Kind;
+
+    // Extensions that decide the format by themselves, whatever the file
+    // holds
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
+
+    // Byte strings a file in this format starts with
+    fn magic(&self) -> &[&[u8]];
+
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>>;
+}
+
+static REGISTRY: &[&dyn Decoder] = &[&Gzip, &Bzip2, &Xz, &Zstd, &ByteOrderMark];
+
+// A known extension decides, and otherwise the magic bytes `head` starts with
+pub(crate) fn find(kind: Kind, filename: &str, head: &[u8]) -> Option<&'static dyn Decoder> {
+    let extension = std::path::Path::new(filename).extension().and_then(|ext| ext.to_str());
+    let decoders = || REGISTRY.iter().copied().filter(move |decoder| decoder.kind() == kind);
+    decoders()
+        .find(|decoder| extension.is_some_and(|ext| decoder.extensions().contains(&ext)))
+        .or_else(|| decoders().find(|decoder| decoder.magic().iter().any(|magic| head.starts_with(magic))))
+}
+
+// Runs `file` through the decoder of `kind` that claims it, if any
+pub(crate) fn decode<'a>(
+    kind: Kind,
+    filename: &str,
+    mut file: Box<dyn BufRead + 'a>,
+) -> MyResult<Box<dyn BufRead + 'a>> {
+    match find(kind, filename, file.fill_buf()?) {
+        Some(decoder) => {
+            debug!("decoding {} as {}", filename, decoder.name());
+            decoder.decode(file)
+        }
+        None => Ok(file),
+    }
+}
+
+// Offsets and columns of a transcoded file count bytes of its UTF-8 text
+pub(crate) fn decode_text<'a>(
+    config: &Config,
+    filename: &str,
+    file: Box<dyn BufRead + 'a>,
+) -> MyResult<Box<dyn BufRead + 'a>> {
+    match config.encoding {
+        Some(encoding) => Ok(transcode(file, Some(encoding))),
+        // Without --encoding a byte order mark still tells how to read the file
+        None if config.sniff_bom => decode(Kind::Encoded, filename, file),
+        None => Ok(file),
+    }
+}
+
+pub(crate) fn has_bom(buf: &[u8]) -> bool {
+    ByteOrderMark.magic().iter().any(|bom| buf.starts_with(bom))
+}
+
+// With no encoding given, the byte order mark picks one; a UTF-8 file just
+// loses its mark and is otherwise passed through untouched
+pub(crate) fn transcode<'a>(file: Box<dyn BufRead + 'a>, encoding: Option<&'static Encoding>) -> Box<dyn BufRead + 'a> {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(encoding)
+        .utf8_passthru(encoding.is_none())
+        .strip_bom(true)
+        .build(file);
+    Box::new(BufReader::new(decoder))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd")))]
+fn unsupported(decoder: &dyn Decoder) -> crate::GreprError {
+    crate::GreprError::Unsupported(format!("grepr was built without {} support", decoder.name()))
+}
+
+// Concatenated streams, as left by appending to a compressed log, are all
+// decoded rather than just the first
+struct Gzip;
+
+impl Decoder for Gzip {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::Compressed
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["gz", "tgz"]
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &[b"\x1F\x8B"]
+    }
+
+    #[cfg(feature = "gzip")]
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Ok(Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(file))))
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn decode<'a>(&self, _file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Err(unsupported(self))
+    }
+}
+
+struct Bzip2;
+
+impl Decoder for Bzip2 {
+    fn name(&self) -> &'static str {
+        "bzip2"
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::Compressed
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bz2", "tbz2"]
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &[b"BZh"]
+    }
+
+    #[cfg(feature = "bzip2")]
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Ok(Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(file))))
+    }
+
+    #[cfg(not(feature = "bzip2"))]
+    fn decode<'a>(&self, _file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Err(unsupported(self))
+    }
+}
+
+struct Xz;
+
+impl Decoder for Xz {
+    fn name(&self) -> &'static str {
+        "xz"
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::Compressed
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["xz", "txz"]
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &[b"\xFD7zXZ\x00"]
+    }
+
+    #[cfg(feature = "xz")]
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Ok(Box::new(BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(
+            file,
+        ))))
+    }
+
+    #[cfg(not(feature = "xz"))]
+    fn decode<'a>(&self, _file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Err(unsupported(self))
+    }
+}
+
+struct Zstd;
+
+impl Decoder for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::Compressed
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["zst", "zstd"]
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &[b"\x28\xB5\x2F\xFD"]
+    }
+
+    #[cfg(feature = "zstd")]
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            file,
+        )?)))
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn decode<'a>(&self, _file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Err(unsupported(self))
+    }
+}
+
+// UTF-16 in either byte order, or UTF-8 with a mark to strip
+struct ByteOrderMark;
+
+impl Decoder for ByteOrderMark {
+    fn name(&self) -> &'static str {
+        "byte order mark"
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::Encoded
+    }
+
+    fn magic(&self) -> &[&[u8]] {
+        &[b"\xEF\xBB\xBF", b"\xFF\xFE", b"\xFE\xFF"]
+    }
+
+    fn decode<'a>(&self, file: Box<dyn BufRead + 'a>) -> MyResult<Box<dyn BufRead + 'a>> {
+        Ok(transcode(file, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, Kind};
+
+    #[test]
+    fn test_find() {
+        let name = |kind, filename, head: &[u8]| find(kind, filename, head).map(|decoder| decoder.name());
+        assert_eq!(name(Kind::Compressed, "log.gz", b""), Some("gzip"));
+        assert_eq!(name(Kind::Compressed, "a.tar.zst", b"plain"), Some("zstd"));
+
+        // Without a known extension the first bytes decide
+        assert_eq!(name(Kind::Compressed, "log.1", b"BZh91AY"), Some("bzip2"));
+        assert_eq!(name(Kind::Compressed, "log.1", b"\xFD7zXZ\x00\x00"), Some("xz"));
+        assert_eq!(name(Kind::Compressed, "log.1", b"plain text"), None);
+
+        // Each kind only looks at its own decoders
+        assert_eq!(name(Kind::Compressed, "notes.txt", b"\xFF\xFEa\x00"), None);
+        assert_eq!(
+            name(Kind::Encoded, "notes.txt", b"\xFF\xFEa\x00"),
+            Some("byte order mark")
+        );
+        assert_eq!(name(Kind::Encoded, "log.gz", b"\x1F\x8B"), None);
+    }
+}
@@ -0,0 +1,390 @@
+// A trigram index over a directory tree, saved under a cache directory, that
+// lets `--use-index` pass over files which cannot contain the patterns. Files
+// the index does not know about, or that changed since it was built, are
+// always searched, so a stale index only ever costs speed.
+
+use crate::{has_bom, transcode, GreprError, MyResult};
+use grepr_core::{find_files, Directories, PatternOptions, WalkOptions};
+use memmap2::Mmap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+// An index file holds, with every integer a little-endian u32 but for the
+// u64 sizes and times:
+//
+//   MAGIC, VERSION, the root's length and bytes
+//   the number of files, of trigrams and of postings in all
+//   per trigram in ascending order: the trigram, its first posting, its count
+//   the postings, file numbers each
+//   per file: its path's length and bytes, its size and modification time
+//
+// Trigrams are fixed-size entries, found by binary search, so a search reads
+// only the postings of its own trigrams out of a mapped file.
+const MAGIC: &[u8; 8] = b"grepridx";
+const VERSION: u32 = 2;
+const TRIGRAM_ENTRY_LEN: usize = 12;
+
+// What a search needs to consult the index: every pattern as a literal of at
+// least one trigram
+#[derive(Debug)]
+pub(crate) struct Query {
+    cache_dir: PathBuf,
+    literals: Vec<Vec<u8>>,
+}
+
+impl Query {
+    // Patterns with any regex syntax, or that fold case, are not ruled out by
+    // missing trigrams, so they turn the index off altogether
+    pub(crate) fn new(cache_dir: PathBuf, patterns: &[String], options: &PatternOptions) -> Option<Query> {
+        if patterns.is_empty() || options.insensitive {
+            return None;
+        }
+        let literals = patterns
+            .iter()
+            .map(|pattern| {
+                (options.fixed_strings || regex::escape(pattern) == *pattern)
+                    .then(|| pattern.as_bytes().to_vec())
+                    .filter(|literal| literal.len() >= 3)
+            })
+            .collect::<Option<_>>()?;
+        Some(Query { cache_dir, literals })
+    }
+}
+
+// An index as loaded for one query: each file's size and modification time
+// when indexed, and whether the query could match it then
+struct Index {
+    root: PathBuf,
+    files: HashMap<String, (u64, u64, bool)>,
+}
+
+pub(crate) struct Filter<'a> {
+    query: &'a Query,
+    indexes: Vec<Index>,
+    dirs: HashMap<PathBuf, Option<usize>>,
+}
+
+impl<'a> Filter<'a> {
+    pub(crate) fn new(query: &'a Query) -> Filter<'a> {
+        Filter {
+            query,
+            indexes: vec![],
+            dirs: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn may_match(&mut self, filename: &str) -> bool {
+        let Some(path) = fs::canonicalize(filename).ok().filter(|_| filename != "-") else {
+            return true;
+        };
+        let dir = path.parent().unwrap_or(&path).to_path_buf();
+        let found = match self.dirs.get(&dir) {
+            Some(&found) => found,
+            None => {
+                let found = self.lookup(&dir);
+                self.dirs.insert(dir, found);
+                found
+            }
+        };
+        let Some(index) = found.map(|i| &self.indexes[i]) else {
+            return true;
+        };
+        let entry = path
+            .strip_prefix(&index.root)
+            .ok()
+            .and_then(|relative| index.files.get(&*relative.to_string_lossy()));
+        match entry {
+            Some(&(size, modified, candidate)) => candidate || stamp(&path) != Some((size, modified)),
+            None => true,
+        }
+    }
+
+    // The index of the nearest enclosing directory that has one
+    fn lookup(&mut self, dir: &Path) -> Option<usize> {
+        for ancestor in dir.ancestors() {
+            if let Some(i) = self.indexes.iter().position(|index| index.root == ancestor) {
+                return Some(i);
+            }
+            if let Some(index) = load(&index_path(&self.query.cache_dir, ancestor), ancestor, &self.query.literals) {
+                self.indexes.push(index);
+                return Some(self.indexes.len() - 1);
+            }
+        }
+        None
+    }
+}
+
+pub(crate) fn default_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("grepr")
+}
+
+// Files under DIR are found as a recursive search with no other options would
+// find them, and any that cannot be read are left out to be searched as usual
+pub(crate) fn build(dir: &str, cache_dir: &Path) -> MyResult<usize> {
+    let root = fs::canonicalize(dir).map_err(|e| GreprError::io(dir, e))?;
+    let mut files = vec![];
+    let mut postings: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    let mut total = 0;
+    for entry in find_files(&[dir.to_string()], Directories::Recurse, &WalkOptions::default()) {
+        let Ok(filename) = entry else {
+            continue;
+        };
+        let path = Path::new(&filename);
+        let Some(relative) = path.strip_prefix(dir).ok().filter(|r| !r.as_os_str().is_empty()) else {
+            continue;
+        };
+        // The stamp is taken first so a file changed while being read looks stale
+        let Some((size, modified)) = stamp(path) else {
+            continue;
+        };
+        let Some(content) = read_searched(path) else {
+            continue;
+        };
+        // Counts are u32s; files past that many postings are searched as usual
+        let trigrams = trigrams(&content);
+        total += trigrams.len();
+        if total > u32::MAX as usize {
+            break;
+        }
+        let id = files.len() as u32;
+        for trigram in trigrams {
+            postings.entry(trigram).or_default().push(id);
+        }
+        files.push((relative.to_string_lossy().into_owned(), size, modified));
+    }
+    let count = files.len();
+    let index = encode(&root.to_string_lossy(), &files, &postings);
+    let path = index_path(cache_dir, &root);
+    fs::create_dir_all(cache_dir).map_err(|e| GreprError::io(cache_dir, e))?;
+    // Written aside and renamed so a search never sees half an index
+    let partial = path.with_extension("tmp");
+    fs::write(&partial, index)
+        .and_then(|_| fs::rename(&partial, &path))
+        .map_err(|e| GreprError::io(&path, e))?;
+    Ok(count)
+}
+
+fn encode(root: &str, files: &[(String, u64, u64)], postings: &BTreeMap<u32, Vec<u32>>) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    let put = |out: &mut Vec<u8>, n: usize| out.extend_from_slice(&(n as u32).to_le_bytes());
+    put(&mut out, VERSION as usize);
+    put(&mut out, root.len());
+    out.extend_from_slice(root.as_bytes());
+    put(&mut out, files.len());
+    put(&mut out, postings.len());
+    put(&mut out, postings.values().map(Vec::len).sum());
+    let mut first = 0;
+    for (&trigram, ids) in postings {
+        put(&mut out, trigram as usize);
+        put(&mut out, first);
+        put(&mut out, ids.len());
+        first += ids.len();
+    }
+    for &id in postings.values().flatten() {
+        put(&mut out, id as usize);
+    }
+    for (path, size, modified) in files {
+        put(&mut out, path.len());
+        out.extend_from_slice(path.as_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&modified.to_le_bytes());
+    }
+    out
+}
+
+// An unreadable, corrupt or foreign index is as good as none
+fn load(path: &Path, root: &Path, literals: &[Vec<u8>]) -> Option<Index> {
+    let file = File::open(path).ok()?;
+    // SAFETY: the map is only ever read, and indexes are replaced by renaming
+    // a new file over them rather than rewritten in place
+    let map = unsafe { Mmap::map(&file).ok()? };
+    let mut reader = Reader(&map);
+    if reader.take(MAGIC.len())? != MAGIC
+        || reader.u32()? != VERSION
+        || reader.string()? != root.to_string_lossy()
+    {
+        return None;
+    }
+    let file_count = reader.u32()? as usize;
+    let trigram_count = reader.u32()? as usize;
+    let posting_count = reader.u32()? as usize;
+    let table = reader.take(trigram_count.checked_mul(TRIGRAM_ENTRY_LEN)?)?;
+    let postings = reader.take(posting_count.checked_mul(4)?)?;
+    let ids_of = |trigram: u32| -> Option<HashSet<u32>> {
+        let entry = |i: usize| Reader(&table[i * TRIGRAM_ENTRY_LEN..]);
+        let (mut low, mut high) = (0, trigram_count);
+        while low < high {
+            let middle = (low + high) / 2;
+            let mut found = entry(middle);
+            match found.u32()?.cmp(&trigram) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let (first, len) = (found.u32()? as usize, found.u32()? as usize);
+                    let mut ids = Reader(postings.get(first.checked_mul(4)?..)?);
+                    return (0..len).map(|_| ids.u32()).collect();
+                }
+            }
+        }
+        Some(HashSet::new())
+    };
+    let mut candidates = HashSet::new();
+    for literal in literals {
+        let mut matching: Option<HashSet<u32>> = None;
+        for trigram in trigrams(literal) {
+            let ids = ids_of(trigram)?;
+            matching = Some(match matching {
+                Some(matching) => &matching & &ids,
+                None => ids,
+            });
+        }
+        candidates.extend(matching.unwrap_or_default());
+    }
+    let files = (0..file_count)
+        .map(|id| {
+            let path = reader.string()?.to_string();
+            let (size, modified) = (reader.u64()?, reader.u64()?);
+            Some((path, (size, modified, candidates.contains(&(id as u32)))))
+        })
+        .collect::<Option<_>>()?;
+    Some(Index {
+        root: root.to_path_buf(),
+        files,
+    })
+}
+
+// Takes the parts of an index off the front of what is left of it, failing
+// on one cut short
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).ok()
+    }
+}
+
+// One index per directory, named by a hash of its canonical path
+fn index_path(cache_dir: &Path, root: &Path) -> PathBuf {
+    let hash = root
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    cache_dir.join(format!("{:016x}.idx", hash))
+}
+
+fn stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), u64::try_from(modified.as_nanos()).ok()?))
+}
+
+// The bytes a search would look at, so a file with a byte order mark is
+// indexed as the UTF-8 it is transcoded to
+fn read_searched(path: &Path) -> Option<Vec<u8>> {
+    let content = fs::read(path).ok()?;
+    if !has_bom(&content) {
+        return Some(content);
+    }
+    let mut decoded = vec![];
+    transcode(Box::new(Cursor::new(content)), None)
+        .read_to_end(&mut decoded)
+        .ok()?;
+    Some(decoded)
+}
+
+fn trigrams(bytes: &[u8]) -> HashSet<u32> {
+    bytes
+        .windows(3)
+        .map(|w| u32::from(w[0]) << 16 | u32::from(w[1]) << 8 | u32::from(w[2]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let patterns = |v: &[&str]| -> Vec<String> { v.iter().map(|s| s.to_string()).collect() };
+        let options = PatternOptions::default();
+        let query = Query::new(PathBuf::new(), &patterns(&["needle", "hay stack"]), &options);
+        assert_eq!(query.unwrap().literals, vec![b"needle".to_vec(), b"hay stack".to_vec()]);
+        assert!(Query::new(PathBuf::new(), &patterns(&["a-b"]), &options).is_none());
+        let fixed = PatternOptions {
+            fixed_strings: true,
+            ..options
+        };
+        let query = Query::new(PathBuf::new(), &patterns(&["a-b"]), &fixed);
+        assert_eq!(query.unwrap().literals, vec![b"a-b".to_vec()]);
+
+        // One pattern the index cannot rule out is enough to search everything
+        assert!(Query::new(PathBuf::new(), &patterns(&["needle", "ne+dle"]), &options).is_none());
+        assert!(Query::new(PathBuf::new(), &patterns(&["ab"]), &fixed).is_none());
+        let insensitive = PatternOptions {
+            insensitive: true,
+            ..options
+        };
+        assert!(Query::new(PathBuf::new(), &patterns(&["needle"]), &insensitive).is_none());
+    }
+
+    #[test]
+    fn test_load() {
+        let contents = [("a.txt", &b"a needle"[..]), ("b.txt", b"needless"), ("c.txt", b"haystack")];
+        let mut postings: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        let mut files = vec![];
+        for (id, (path, content)) in contents.iter().enumerate() {
+            for trigram in trigrams(content) {
+                postings.entry(trigram).or_default().push(id as u32);
+            }
+            files.push((path.to_string(), content.len() as u64, id as u64));
+        }
+        let encoded = encode("/repo", &files, &postings);
+        let path = env::temp_dir().join(format!("grepr-test-load-{}.idx", std::process::id()));
+        fs::write(&path, &encoded).unwrap();
+        let candidates = |literals: &[&[u8]]| {
+            let literals: Vec<_> = literals.iter().map(|literal| literal.to_vec()).collect();
+            let index = load(&path, Path::new("/repo"), &literals)?;
+            let mut found: Vec<_> = index.files.iter().filter(|(_, file)| file.2).map(|(path, _)| path.clone()).collect();
+            found.sort();
+            Some(found)
+        };
+        assert_eq!(candidates(&[b"needle"]), Some(vec!["a.txt".to_string(), "b.txt".to_string()]));
+        assert_eq!(candidates(&[b"needless", b"stack"]), Some(vec!["b.txt".to_string(), "c.txt".to_string()]));
+        assert_eq!(candidates(&[b"nothing"]), Some(vec![]));
+        assert!(load(&path, Path::new("/elsewhere"), &[]).is_none());
+
+        // A truncated index is no index at all
+        fs::write(&path, &encoded[..encoded.len() - 1]).unwrap();
+        assert!(load(&path, Path::new("/repo"), &[]).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,288 +1,876 @@
-use clap::{App, Arg};
-use regex::{Regex, RegexBuilder};
-use walkdir::WalkDir;
+use args::{Args, Command, IndexCommand, IndexDir};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use clap_complete::Shell;
+use encoding_rs::Encoding;
+use globset::GlobSet;
+use grepr_core::{
+    build_globset, build_hyperscan, build_matcher, build_overrides, build_types, Directories, Matcher,
+    PatternOptions, SortBy, WalkOptions,
+};
+use ignore::types::FileTypeDef;
 use std::{
-    error::Error,
-    fs::{self, File},
-    io::{self, BufRead, BufReader},
-    mem,
+    env,
+    error::Error as _,
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+    path::PathBuf,
+    thread,
+    time::Duration,
 };
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+
+mod args;
+#[cfg(feature = "tokio")]
+pub mod asynch;
+mod bench;
+mod builder;
+mod cancel;
+mod decode;
+mod index;
+mod printer;
+mod progress;
+mod search;
+mod sink;
+
+use bench::Bench;
+pub use builder::ConfigBuilder;
+pub use cancel::CancellationToken;
+use decode::{has_bom, transcode};
+pub use grepr_core::GreprError;
+use progress::ProgressHook;
+pub use progress::Progress;
+use search::search_to;
+pub use search::{run_with_sink, search_reader, search_slice, search_with};
+pub use sink::{FileSummary, Sink};
+
+type MyResult<T> = Result<T, GreprError>;
+
+pub const EXIT_MATCH: i32 = 0;
+pub const EXIT_NO_MATCH: i32 = 1;
+/// Bad arguments or patterns, or some file that could not be searched
+pub const EXIT_ERROR: i32 = 2;
+/// Reading or writing failed badly enough to stop the search altogether,
+/// such as a pattern file that cannot be read or output that cannot be written
+pub const EXIT_IO_ERROR: i32 = 3;
+
+/// The exit status for a search that failed with `error`.
+pub fn exit_code(error: &GreprError) -> i32 {
+    match error {
+        GreprError::Io { .. } | GreprError::IsADirectory(_) | GreprError::Walk(_) | GreprError::Preprocessor { .. } => {
+            EXIT_IO_ERROR
+        }
+        _ => EXIT_ERROR,
+    }
+}
 
-type MyResult<T> = Result<T, Box<dyn Error>>;
+const MATCH_COLOR: &str = "01;31";
+const FILENAME_COLOR: &str = "35";
+const LINE_NUMBER_COLOR: &str = "32";
+const BYTE_OFFSET_COLOR: &str = "32";
+const SEPARATOR_COLOR: &str = "36";
 
+/// Everything a search needs, parsed and compiled. With the `serde` feature
+/// one can be deserialized from the fields of a [`ConfigBuilder`]; compiled
+/// patterns cannot be written back out, so serialize the builder instead.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(try_from = "ConfigBuilder"))]
 pub struct Config {
-    pattern: Regex,
+    pattern: Box<dyn Matcher>,
     files: Vec<String>,
-    recursive: bool,
+    directories: Directories,
+    walk_options: WalkOptions,
     count: bool,
+    count_matches: bool,
     invert_match: bool,
+    line_number: bool,
+    after_context: usize,
+    before_context: usize,
+    color: bool,
+    colors: Colors,
+    files_with_matches: bool,
+    files_without_match: bool,
+    only_matching: bool,
+    max_count: Option<usize>,
+    quiet: bool,
+    byte_offset: bool,
+    with_filename: Option<bool>,
+    label: Option<String>,
+    null: bool,
+    null_data: bool,
+    binary_files: BinaryFiles,
+    no_messages: bool,
+    group_separator: Option<String>,
+    initial_tab: bool,
+    line_buffered: bool,
+    type_list: Option<Vec<FileTypeDef>>,
+    list_candidates: bool,
+    multiline: bool,
+    replace: Option<String>,
+    vimgrep: bool,
+    column: bool,
+    heading: bool,
+    json: bool,
+    stats: bool,
+    threads: usize,
+    mmap: Option<bool>,
+    encoding: Option<&'static Encoding>,
+    sniff_bom: bool,
+    search_zip: bool,
+    pre: Option<String>,
+    pre_glob: Option<GlobSet>,
+    passthru: bool,
+    trim: bool,
+    hyperlink_format: Option<String>,
+    max_columns: Option<usize>,
+    max_columns_preview: bool,
+    build_index: Option<(String, PathBuf)>,
+    generate: Option<Generate>,
+    index_query: Option<index::Query>,
+    cancellation: Option<CancellationToken>,
+    progress: Option<ProgressHook>,
+    log_level: Option<Level>,
+    bench: Option<Bench>,
 }
 
-pub fn get_args() -> MyResult<Config> {
-    let matches = App::new("grepr")
-        .version("0.1.0")
-        .author("Ken C.Y. Leung <kenleung5e28@gmail.com>")
-        .about("Rust grep")
-        .arg(
-            Arg::with_name("pattern")
-                .value_name("PATTERN")
-                .help("Search pattern")
-                .takes_value(true)
-                .required(true)
-        )
-        .arg(
-            Arg::with_name("files")
-                .value_name("FILE")
-                .help("Input file(s)")
-                .default_value("-")
-                .multiple(true)
-        )
-        .arg(
-            Arg::with_name("count")
-                .short("c")
-                .long("count")
-                .help("Count occurrences")
-                .takes_value(false)
-        )
-        .arg(
-            Arg::with_name("insensitive")
-                .short("i")
-                .long("insensitive")
-                .help("Case-insensitive")
-                .takes_value(false)
-        )
-        .arg(
-            Arg::with_name("invert_match")
-                .short("v")
-                .long("invert-match")
-                .help("Invert match")
-                .takes_value(false)
-        )
-        .arg(
-            Arg::with_name("recursive")
-                .short("r")
-                .long("recursive")
-                .help("Recursive search")
-                .takes_value(false)
-        )
-        .get_matches();
-
-    let pattern = matches.value_of("pattern").unwrap();
-    Ok(Config {
-        pattern: RegexBuilder::new(pattern)
-            .case_insensitive(matches.is_present("insensitive"))
-            .build()
-            .map_err(|_| -> Box<dyn Error> {
-                From::from(format!("Invalid pattern \"{}\"", pattern))
-            })?,
-        files: matches.values_of_lossy("files").unwrap(),
-        recursive: matches.is_present("recursive"),
-        count: matches.is_present("count"),
-        invert_match: matches.is_present("invert_match"),
-    })
+impl Config {
+    fn check_cancelled(&self) -> MyResult<()> {
+        match &self.cancellation {
+            Some(token) if token.is_cancelled() => Err(GreprError::Cancelled),
+            _ => Ok(()),
+        }
+    }
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let entries = find_files(&config.files, config.recursive);
-    let is_single_file = entries.len() == 1;
-    let print_line = |s: &str, filename: &str| {
-        if is_single_file || filename == "-" {
-            print!("{}", s);
-        } else {
-            print!("{}:{}", filename, s);
+// SGR sequences for each part of the output, named as in GNU's GREP_COLORS
+#[derive(Debug, PartialEq)]
+struct Colors {
+    selected_match: String,
+    context_match: String,
+    filename: String,
+    line_number: String,
+    column: String,
+    byte_offset: String,
+    separator: String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            selected_match: MATCH_COLOR.to_string(),
+            context_match: MATCH_COLOR.to_string(),
+            filename: FILENAME_COLOR.to_string(),
+            line_number: LINE_NUMBER_COLOR.to_string(),
+            column: LINE_NUMBER_COLOR.to_string(),
+            byte_offset: BYTE_OFFSET_COLOR.to_string(),
+            separator: SEPARATOR_COLOR.to_string(),
         }
-    };
-    for entry in entries {
-        match entry {
-            Err(e) => eprintln!("{}", e),
-            Ok(filename) => match open(&filename) {
-                Err(e) => eprintln!("{}: {}", filename, e),
-                Ok(file) => {
-                    let matches = find_lines(
-                        file, 
-                        &config.pattern,
-                        config.invert_match,
-                    );
-                    if matches.is_err() {
-                        eprintln!("{}: {}", filename, matches.unwrap_err());
-                        continue;
-                    }
-                    let matches = matches.unwrap();
-                    if config.count {
-                        print_line(&format!("{}\n", matches.len()), &filename);
-                    } else {
-                        for s in matches {
-                            print_line(&s, &filename);
-                        }
-                    }
+    }
+}
+
+impl Colors {
+    // Apply a `key=sgr:key=sgr` list, returning the first entry not understood
+    fn apply<'a>(&mut self, spec: &'a str) -> Result<(), &'a str> {
+        for entry in spec.split(':').filter(|entry| !entry.is_empty()) {
+            let (key, sgr) = entry.split_once('=').ok_or(entry)?;
+            if !sgr.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                return Err(entry);
+            }
+            let sgr = sgr.to_string();
+            match key {
+                "mt" => {
+                    self.selected_match = sgr.clone();
+                    self.context_match = sgr;
                 }
+                "ms" => self.selected_match = sgr,
+                "mc" => self.context_match = sgr,
+                "fn" => self.filename = sgr,
+                "ln" => self.line_number = sgr,
+                "bn" => self.byte_offset = sgr,
+                "se" => self.separator = sgr,
+                _ => return Err(entry),
             }
         }
+        Ok(())
     }
-    Ok(())
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+// What the hidden --generate-* flags print instead of searching, made from
+// the same definitions the command line is parsed with
+#[derive(Debug, Clone, Copy)]
+enum Generate {
+    Completions(Shell),
+    Man,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+enum BinaryFiles {
+    Binary,
+    Text,
+    WithoutMatch,
+}
+
+/// Totals over every file a search looked at, as `run` returns them and
+/// `--stats` and the JSON summary report them.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchStats {
+    /// Files searched, standard input counting as one
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub bytes_searched: usize,
+    /// Files that could not be found, opened or read
+    pub errors: usize,
+    pub elapsed: Duration,
+    // Whether anything was selected, which --files and --type-list count as
+    // having done whenever they list something
+    matched: bool,
+    quiet: bool,
+}
+
+impl SearchStats {
+    /// The process exit status: `EXIT_MATCH` if any line was selected,
+    /// `EXIT_NO_MATCH` if none was and `EXIT_ERROR` if some file could not
+    /// be searched, unless `-q` found a match anyway.
+    pub fn exit_code(&self) -> i32 {
+        if self.errors > 0 && !(self.quiet && self.matched) {
+            EXIT_ERROR
+        } else if self.matched {
+            EXIT_MATCH
+        } else {
+            EXIT_NO_MATCH
+        }
+    }
+
+    fn add(&mut self, other: &SearchStats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.bytes_searched += other.bytes_searched;
     }
 }
 
-fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
-    if recursive {
-        paths.iter()
-            .flat_map(|path| if path.as_str() == "-" {
-                    vec![Ok("-".to_string())]
-                } else {
-                    WalkDir::new(path)
-                        .into_iter()
-                        .filter_map(|entry| entry
-                            .map_err(|e| From::from(format!("{}", e)))
-                            .map(|entry| if entry.file_type().is_dir() {
-                                None
-                            } else {
-                                Some(String::from(entry.path().to_string_lossy()))
-                            })
-                            .transpose()
-                        )
-                        .collect()
-                }
-            )
-            .collect()
+pub fn get_args() -> MyResult<Config> {
+    let args = env::args().collect();
+    let args = expand_context_shorthand(prepend_default_opts(args, env::var("GREPR_OPTS").ok().as_deref())?);
+    let args = Args::try_parse_from(args).or_else(|e| match e.kind() {
+        ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => e.exit(),
+        _ => Err(GreprError::InvalidArgument(match e.source() {
+            // A value our own parsers turned down is reported as they word it
+            Some(source) if source.is::<GreprError>() => source.to_string(),
+            _ => e.to_string().trim_end().to_string(),
+        })),
+    })?;
+
+    let context = args.context.unwrap_or(0);
+    // With -e, -f or --files the first positional is no longer a pattern but a file
+    let mut files = vec![];
+    let mut patterns = args.regexp.clone();
+    for filename in &args.pattern_file {
+        patterns.extend(read_patterns(filename)?);
+    }
+    if !args.regexp.is_empty() || !args.pattern_file.is_empty() || args.list_candidates {
+        files.extend(args.pattern);
     } else {
-        paths.iter()
-            .map(|path| if path.as_str() == "-" {
-                Ok("-".to_string())
-            } else { 
-                let metadata = fs::metadata(path)
-                    .map_err(|e| -> Box<dyn Error> { 
-                        From::from(format!("{}: {}", path, e))
-                    })?;
-                if metadata.is_dir() {
-                    Err(From::from(format!("{} is a directory", path)))
-                } else {
-                    Ok(path.to_owned())
-                }
-            })
-            .collect()
+        patterns.extend(args.pattern);
+    }
+    // A file list stands in for the default of standard input
+    let lists: Vec<_> = (args.files_from.iter().map(|list| (list, b'\n')))
+        .chain(args.files0_from.iter().map(|list| (list, b'\0')))
+        .collect();
+    if !args.files.is_empty() {
+        files.extend(args.files);
+    } else if files.is_empty() && lists.is_empty() {
+        files.push("-".to_string());
+    }
+    for (list, terminator) in lists {
+        files.extend(read_file_list(list, terminator)?);
+    }
+    let mut exclude = args.exclude;
+    let mut exclude_dir = args.exclude_dir;
+    for filename in &args.exclude_from {
+        // As in .gitignore, a trailing slash only matches directories
+        for glob in read_patterns(filename)? {
+            let glob = glob.trim();
+            if glob.is_empty() || glob.starts_with('#') {
+                continue;
+            }
+            match glob.strip_suffix('/') {
+                Some(dir) => exclude_dir.push(dir.to_string()),
+                None => exclude.push(glob.to_string()),
+            }
+        }
+    }
+    // Like GNU grep, entries in GREP_COLORS that make no sense are ignored
+    let mut colors = Colors::default();
+    if let Ok(spec) = env::var("GREP_COLORS") {
+        for entry in spec.split(':') {
+            let _ = colors.apply(entry);
+        }
+    }
+    for spec in &args.colors {
+        colors
+            .apply(spec)
+            .map_err(|entry| GreprError::InvalidArgument(format!("Invalid color spec \"{}\"", entry)))?;
+    }
+    let types = build_types(&args.type_add, &args.types, &args.type_not)?;
+    let insensitive = args.insensitive || (args.smart_case && !patterns.iter().any(|p| has_uppercase(p)));
+    let pattern_options = PatternOptions {
+        fixed_strings: args.fixed_strings,
+        insensitive,
+        word_regexp: args.word_regexp,
+        line_regexp: args.line_regexp,
+        pcre: args.pcre,
+        multiline: args.multiline,
+        captures: args.replace.is_some(),
+        no_unicode: args.no_unicode,
+        size_limit: args.regex_size_limit,
+        dfa_size_limit: args.dfa_size_limit,
+    };
+    let hyperscan = args.engine.as_deref() == Some("hyperscan");
+    if pattern_options.captures && hyperscan {
+        return Err(GreprError::Unsupported(
+            "--replace is not supported by the hyperscan engine".to_string(),
+        ));
+    }
+    // Standard input can only be read once, leaving later runs nothing to time
+    if args.bench.is_some() && files.iter().any(|file| file == "-") {
+        return Err(GreprError::Unsupported(
+            "--bench is not supported when reading standard input".to_string(),
+        ));
+    }
+    let index_dir = |index_dir: IndexDir| index_dir.index_dir.unwrap_or_else(index::default_dir);
+    let build_index = args.command.map(|command| match command {
+        Command::Index {
+            command: IndexCommand::Build { dir, index_dir: build_dir },
+        } => (dir, index_dir(build_dir)),
+    });
+    // Only a search that prints nothing for a file without matches may skip one
+    let index_query = if args.use_index
+        && !args.invert_match
+        && !args.count
+        && !args.count_matches
+        && !args.files_without_match
+        && !args.passthru
+        && !args.search_zip
+        && args.pre.is_none()
+        && args.encoding.is_none()
+    {
+        index::Query::new(index_dir(args.index_dir), &patterns, &pattern_options)
+    } else {
+        None
+    };
+    let directories = match args.directories.as_deref() {
+        _ if args.recursive || args.dereference_recursive => Directories::Recurse,
+        Some("skip") => Directories::Skip,
+        Some("recurse") => Directories::Recurse,
+        _ => Directories::Read,
+    };
+    // Zero, like leaving it out, uses every core the system offers
+    let threads = args
+        .threads
+        .filter(|&threads| threads > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    Ok(Config {
+        pattern: if hyperscan && !patterns.is_empty() {
+            build_hyperscan(&patterns, &pattern_options)?
+        } else {
+            build_matcher(&patterns, &pattern_options)?
+        },
+        files,
+        directories,
+        walk_options: WalkOptions {
+            include: (!args.include.is_empty())
+                .then(|| build_globset(&args.include))
+                .transpose()?,
+            exclude: build_globset(&exclude)?,
+            exclude_dir: build_globset(&exclude_dir)?,
+            globs: build_overrides(&args.glob, &args.iglob)?,
+            types: if args.types.is_empty() && args.type_not.is_empty() {
+                None
+            } else {
+                Some(types.build().map_err(GreprError::Walk)?)
+            },
+            max_depth: args.max_depth,
+            max_filesize: args.max_filesize,
+            follow_links: args.follow || args.dereference_recursive,
+            one_file_system: args.one_file_system,
+            hidden: args.hidden,
+            no_ignore: args.no_ignore,
+            no_ignore_vcs: args.no_ignore_vcs,
+            no_ignore_parent: args.no_ignore_parent,
+            sort: match (args.sort, args.sortr) {
+                (Some(key), _) => Some((key, false)),
+                (_, Some(key)) => Some((key, true)),
+                _ => None,
+            },
+            threads,
+        },
+        count: args.count || args.count_matches,
+        count_matches: args.count_matches,
+        invert_match: args.invert_match,
+        line_number: args.line_number || args.vimgrep,
+        after_context: args.after_context.unwrap_or(context),
+        before_context: args.before_context.unwrap_or(context),
+        color: match args.color.as_deref() {
+            Some("always") => true,
+            Some("auto") => io::stdout().is_terminal(),
+            _ => false,
+        },
+        colors,
+        files_with_matches: args.files_with_matches,
+        files_without_match: args.files_without_match,
+        only_matching: args.only_matching,
+        max_count: args.max_count,
+        quiet: args.quiet,
+        byte_offset: args.byte_offset,
+        with_filename: if args.with_filename || args.vimgrep {
+            Some(true)
+        } else if args.no_filename {
+            Some(false)
+        } else {
+            None
+        },
+        label: args.label,
+        null: args.null,
+        null_data: args.null_data,
+        binary_files: match args.binary_files {
+            Some(binary_files) => binary_files,
+            None if args.text => BinaryFiles::Text,
+            None if args.skip_binary => BinaryFiles::WithoutMatch,
+            None if directories == Directories::Recurse => BinaryFiles::WithoutMatch,
+            None => BinaryFiles::Binary,
+        },
+        no_messages: args.no_messages,
+        group_separator: if args.no_group_separator {
+            None
+        } else {
+            Some(args.group_separator.unwrap_or_else(|| "--".to_string()))
+        },
+        initial_tab: args.initial_tab,
+        line_buffered: args.line_buffered,
+        type_list: if args.type_list {
+            Some(types.definitions())
+        } else {
+            None
+        },
+        list_candidates: args.list_candidates,
+        multiline: pattern_options.multiline,
+        replace: args.replace,
+        vimgrep: args.vimgrep,
+        column: args.column,
+        json: args.json,
+        stats: args.stats,
+        threads,
+        encoding: match args.encoding.as_deref() {
+            None | Some("auto") | Some("none") => None,
+            Some(label) => Some(
+                Encoding::for_label(label.as_bytes())
+                    .ok_or_else(|| GreprError::InvalidArgument(format!("Unknown encoding \"{}\"", label)))?,
+            ),
+        },
+        sniff_bom: args.encoding.as_deref() != Some("none"),
+        search_zip: args.search_zip,
+        pre: args.pre,
+        passthru: args.passthru,
+        trim: args.trim,
+        // Zero lifts the limit, as if none were given
+        max_columns: args.max_columns.filter(|&max| max > 0),
+        max_columns_preview: args.max_columns_preview,
+        build_index,
+        index_query,
+        generate: match args.generate_completions {
+            Some(shell) => Some(Generate::Completions(shell)),
+            None if args.generate_man => Some(Generate::Man),
+            None => None,
+        },
+        cancellation: None,
+        progress: None,
+        log_level: match (args.debug, args.verbose) {
+            (true, _) => Some(Level::DEBUG),
+            (false, true) => Some(Level::INFO),
+            (false, false) => None,
+        },
+        bench: args.bench.map(Bench::new),
+        hyperlink_format: args
+            .hyperlink_format
+            .filter(|format| format != "none")
+            .map(|format| parse_hyperlink_format(&format))
+            .transpose()?,
+        pre_glob: (!args.pre_glob.is_empty())
+            .then(|| build_globset(&args.pre_glob))
+            .transpose()?,
+        mmap: if args.mmap {
+            Some(true)
+        } else if args.no_mmap {
+            Some(false)
+        } else {
+            None
+        },
+        heading: if args.heading {
+            true
+        } else if args.no_heading || args.vimgrep || args.json {
+            false
+        } else {
+            io::stdout().is_terminal()
+        },
+    })
+}
+
+// GREPR_OPTS goes right after the program name, so anything given on the
+// command line overrides it; an `index` subcommand is left to itself
+fn prepend_default_opts(mut args: Vec<String>, opts: Option<&str>) -> MyResult<Vec<String>> {
+    let Some(opts) = opts.filter(|_| args.get(1).is_none_or(|arg| arg != "index")) else {
+        return Ok(args);
+    };
+    let defaults = split_opts(opts)
+        .ok_or_else(|| GreprError::InvalidArgument(format!("Invalid GREPR_OPTS \"{}\"", opts)))?;
+    let at = args.len().min(1);
+    args.splice(at..at, defaults);
+    Ok(args)
+}
+
+// Splits on whitespace as a shell would, with quotes and backslashes to keep
+// a value such as a glob in one piece; an unclosed quote gives None
+fn split_opts(opts: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = opts.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => word.get_or_insert_with(String::new).push(chars.next().unwrap_or('\\')),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return None;
     }
+    words.extend(word);
+    Some(words)
 }
 
-fn find_lines<T: BufRead>(mut file: T, pattern: &Regex, invert_match: bool) -> MyResult<Vec<String>> {
-    let mut lines = vec![];
-    let mut buf = String::new();
-    loop {
-        let bytes_read = file.read_line(&mut buf)?;
-        if bytes_read == 0 {
+// Rewrite GNU-style `-NUM` into `-C NUM`, leaving everything after `--` alone
+fn expand_context_shorthand<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    let mut expanded = vec![];
+    let mut args = args.into_iter();
+    for arg in args.by_ref() {
+        if arg == "--" {
+            expanded.push(arg);
             break;
         }
-        if pattern.is_match(&buf) == !invert_match {
-            lines.push(mem::take(&mut buf));
+        match arg.strip_prefix('-') {
+            Some(num) if !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()) => {
+                expanded.push("-C".to_string());
+                expanded.push(num.to_string());
+            }
+            _ => expanded.push(arg),
+        }
+    }
+    expanded.extend(args);
+    expanded
+}
+
+fn read_patterns(filename: &str) -> MyResult<Vec<String>> {
+    open(filename)
+        .and_then(|file| file.lines().collect())
+        .map_err(|e| GreprError::io(filename, e))
+}
+
+// Expands the named formats and checks that a custom one only uses the
+// {path}, {host}, {line} and {column} variables
+fn parse_hyperlink_format(format: &str) -> MyResult<String> {
+    let format = match format {
+        "default" | "file" => "file://{host}{path}",
+        "vscode" => "vscode://file{path}:{line}:{column}",
+        "cursor" => "cursor://file{path}:{line}:{column}",
+        "idea" => "idea://open?file={path}&line={line}",
+        "textmate" => "txmt://open?url=file://{path}&line={line}&column={column}",
+        other => other,
+    };
+    let invalid = || GreprError::InvalidArgument(format!("Invalid hyperlink format \"{}\"", format));
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+        if !matches!(&rest[start + 1..end], "path" | "host" | "line" | "column") {
+            return Err(invalid());
+        }
+        rest = &rest[end + 1..];
+    }
+    if !format.contains("{path}") {
+        return Err(invalid());
+    }
+    Ok(format.to_string())
+}
+
+// The paths in a --files-from or --files0-from list, skipping empty entries;
+// only a NUL-separated list can hold paths with newlines in them
+fn read_file_list(filename: &str, terminator: u8) -> MyResult<Vec<String>> {
+    let mut list = vec![];
+    open(filename)
+        .and_then(|mut file| file.read_to_end(&mut list))
+        .map_err(|e| GreprError::io(filename, e))?;
+    Ok(list
+        .split(|&b| b == terminator)
+        .map(|path| match terminator {
+            b'\n' => path.strip_suffix(b"\r").unwrap_or(path),
+            _ => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(|path| String::from_utf8_lossy(path).into_owned())
+        .collect())
+}
+
+// Whether a pattern spells out an uppercase letter, not counting escapes
+// such as \S or \W
+fn has_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
         }
-        buf.clear();
     }
-    Ok(lines)
+    false
+}
+
+// A byte count with an optional K, M or G suffix in powers of 1024
+fn parse_size(val: &str) -> MyResult<u64> {
+    let (digits, shift) = match val.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&val[..i], 10),
+        Some((i, 'm' | 'M')) => (&val[..i], 20),
+        Some((i, 'g' | 'G')) => (&val[..i], 30),
+        _ => (val, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| GreprError::InvalidArgument(format!("Invalid file size \"{}\"", val)))
+}
+
+fn parse_regex_limit(val: &str, name: &str) -> MyResult<usize> {
+    parse_size(val)
+        .ok()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| GreprError::InvalidArgument(format!("Invalid {} \"{}\"", name, val)))
+}
+
+// A parser for a count such as --max-count, naming it in the error
+fn parse_count(name: &'static str) -> impl Fn(&str) -> MyResult<usize> + Clone + Send + Sync + 'static {
+    move |val| {
+        val.parse()
+            .map_err(|_| GreprError::InvalidArgument(format!("Invalid {} \"{}\"", name, val)))
+    }
+}
+
+fn parse_context_length(val: &str) -> MyResult<usize> {
+    val.parse()
+        .map_err(|_| GreprError::InvalidArgument(format!("Invalid context length \"{}\"", val)))
+}
+
+/// Searches as configured, printing results to standard output, and
+/// returns what the search came to.
+pub fn run(config: Config) -> MyResult<SearchStats> {
+    if let Some(level) = config.log_level {
+        init_logging(level);
+    }
+    run_with_writer(config, &mut io::stdout())
+}
+
+// Logs go to standard error. The ignore crate logs each file its .gitignore
+// and hidden-file rules skip at its debug level, which --verbose lets through
+// as well. Under --debug each file's search is a span, logged with its
+// timings as it closes.
+fn init_logging(level: Level) {
+    let span_events = match level {
+        Level::DEBUG => FmtSpan::CLOSE,
+        _ => FmtSpan::NONE,
+    };
+    let filter = Targets::new().with_default(level).with_target("ignore::walk", Level::DEBUG);
+    // Left alone if something has set a subscriber already
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .with_span_events(span_events)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        // Timings are kept for spans, only the timestamps go
+        .with_timer(())
+        .finish()
+        .with(filter)
+        .try_init();
+}
+
+/// Runs as `run` does, writing what would go to standard output to `out`.
+/// Warnings still go to standard error.
+pub fn run_with_writer<W: Write>(config: Config, out: &mut W) -> MyResult<SearchStats> {
+    let mut stats = SearchStats {
+        quiet: config.quiet,
+        ..SearchStats::default()
+    };
+    if let Some(definitions) = &config.type_list {
+        for def in definitions {
+            writeln!(out, "{}: {}", def.name(), def.globs().join(", "))?;
+        }
+        stats.matched = true;
+        return Ok(stats);
+    }
+    if let Some(generate) = config.generate {
+        match generate {
+            Generate::Completions(shell) => {
+                // Generated into memory, as clap_complete panics on a failed write
+                let mut script = vec![];
+                clap_complete::generate(shell, &mut Args::command(), "grepr", &mut script);
+                out.write_all(&script)?;
+            }
+            Generate::Man => clap_mangen::Man::new(Args::command()).render(out)?,
+        }
+        stats.matched = true;
+        return Ok(stats);
+    }
+    if let Some((dir, index_dir)) = &config.build_index {
+        let count = index::build(dir, index_dir)?;
+        writeln!(out, "Indexed {} files under {}", count, dir)?;
+        stats.matched = true;
+        return Ok(stats);
+    }
+    match &config.bench {
+        Some(bench) => bench.run(&config, out),
+        None => search_to(&config, out),
+    }
+}
+
+fn open(filename: &str) -> io::Result<Box<dyn BufRead>> {
+    match filename {
+        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
+        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+    }
+}
+
+fn parse_sort_by(key: &str) -> SortBy {
+    match key {
+        "modified" => SortBy::Modified,
+        "created" => SortBy::Created,
+        "size" => SortBy::Size,
+        _ => SortBy::Path,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{find_files, find_lines};
-    use rand::{distributions::Alphanumeric, Rng};
-    use regex::{Regex, RegexBuilder};
-    use std::io::Cursor;
+    use super::{
+        expand_context_shorthand, has_uppercase, parse_size, prepend_default_opts, run_with_writer, split_opts, Colors,
+        Config, EXIT_MATCH, EXIT_NO_MATCH,
+    };
 
     #[test]
-    fn test_find_files() {
-        // Verify that the function treats dash as a file
-        let files = find_files(&["-".to_string()], false);
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0].as_ref().unwrap(), "-");
-
-        // Verify that the function finds a file known to exist
-        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false);
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
-
-        // The function should reject a directory without the recursive option
-        let files = find_files(&["./tests/inputs".to_string()], false);
-        assert_eq!(files.len(), 1);
-        if let Err(e) = &files[0] {
-            assert_eq!(e.to_string(), "./tests/inputs is a directory");
+    fn test_colors_apply() {
+        let mut colors = Colors::default();
+        assert_eq!(colors.apply("mt=01;32:fn=:ln=33"), Ok(()));
+        assert_eq!(colors.selected_match, "01;32");
+        assert_eq!(colors.context_match, "01;32");
+        assert_eq!(colors.filename, "");
+        assert_eq!(colors.line_number, "33");
+        assert_eq!(colors.separator, "36");
+
+        // Later entries win, and the first bad one is reported
+        assert_eq!(colors.apply("mc=35:se=red:xx=1"), Err("se=red"));
+        assert_eq!(colors.context_match, "35");
+        assert_eq!(colors.apply("rv"), Err("rv"));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("3m").unwrap(), 3 << 20);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        for bad in ["", "K", "1.5M", "10T", "-1"] {
+            let err = parse_size(bad).unwrap_err();
+            assert_eq!(err.to_string(), format!("Invalid file size \"{}\"", bad));
         }
+    }
 
-        // Verify the function recurses to find four files in the directory
-        let res = find_files(&["./tests/inputs".to_string()], true);
-        let mut files: Vec<String> = res.iter()
-            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
-            .collect();
-        files.sort();
-        assert_eq!(files.len(), 4);
-        assert_eq!(files, vec![
-            "./tests/inputs/bustle.txt",
-            "./tests/inputs/empty.txt",
-            "./tests/inputs/fox.txt",
-            "./tests/inputs/nobody.txt",
-        ]);
-
-        // Verify the function finds the file and recurses to find four files in the directory
-        let res = find_files(&["./tests/cli.rs".to_string(), "./tests/inputs".to_string()], true);
-        let mut files: Vec<String> = res.iter()
-            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
-            .collect();
-        files.sort();
-        assert_eq!(files.len(), 5);
-        assert_eq!(files, vec![
-            "./tests/cli.rs",
-            "./tests/inputs/bustle.txt",
-            "./tests/inputs/empty.txt",
-            "./tests/inputs/fox.txt",
-            "./tests/inputs/nobody.txt",
-        ]);
-
-        // Generate a random string to represent a nonexistent file
-        let bad: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(7)
-            .map(char::from)
-            .collect();
-        
-        // Verify that the function returns the bad file as an error
-        let files = find_files(&[bad], false);
-        assert_eq!(files.len(), 1);
-        assert!(files[0].is_err());
+    #[test]
+    fn test_expand_context_shorthand() {
+        let args = |v: &[&str]| -> Vec<String> {
+            v.iter().map(|s| s.to_string()).collect()
+        };
+
+        // A bare -NUM becomes -C NUM, other options pass through untouched
+        assert_eq!(
+            expand_context_shorthand(args(&["grepr", "-2", "-n", "foo"])),
+            args(&["grepr", "-C", "2", "-n", "foo"]),
+        );
+
+        // Nothing after -- is rewritten
+        assert_eq!(
+            expand_context_shorthand(args(&["grepr", "--", "-2"])),
+            args(&["grepr", "--", "-2"]),
+        );
     }
 
     #[test]
-    fn test_find_lines() {
-        let text = b"Lorem\nIpsum\r\nDOLOR";
-
-        // The pattern _or_ should match the one line "Lorem"
-        let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, false);
-        assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
-
-        // When inverted, the function should match the other two lines
-        let matches = find_lines(Cursor::new(&text), &re1, true);
-        assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
-
-        // This regex will be case-insensitive
-        let re2 = RegexBuilder::new("or")
-            .case_insensitive(true)
+    fn test_split_opts() {
+        assert_eq!(split_opts("  -i\t--color=auto "), Some(vec!["-i".to_string(), "--color=auto".to_string()]));
+        assert_eq!(
+            split_opts(r#"--exclude-dir 'my dir' -g "*.{rs,\"md\"}" a\ b ''"#),
+            Some(["--exclude-dir", "my dir", "-g", "*.{rs,\"md\"}", "a b", ""].map(String::from).to_vec())
+        );
+        assert_eq!(split_opts("-e 'oops"), None);
+
+        let args = |v: &[&str]| -> Vec<String> { v.iter().map(|s| s.to_string()).collect() };
+        assert_eq!(
+            prepend_default_opts(args(&["grepr", "-n", "fox"]), Some("-i -s")).unwrap(),
+            args(&["grepr", "-i", "-s", "-n", "fox"])
+        );
+        assert_eq!(
+            prepend_default_opts(args(&["grepr", "index", "build", "."]), Some("-i")).unwrap(),
+            args(&["grepr", "index", "build", "."])
+        );
+        assert!(prepend_default_opts(args(&["grepr", "fox"]), Some("\"-i")).is_err());
+    }
+
+    #[test]
+    fn test_has_uppercase() {
+        assert!(!has_uppercase("fox"));
+        assert!(has_uppercase("Fox"));
+        assert!(has_uppercase("straße|É"));
+        assert!(!has_uppercase(r"\S+\W\d"));
+        assert!(has_uppercase(r"\\S"));
+    }
+
+    #[test]
+    fn test_run_with_writer() {
+        let config = Config::builder()
+            .pattern("sweeping")
+            .file("tests/inputs/bustle.txt")
+            .line_number(true)
+            .build()
+            .unwrap();
+        let mut out = vec![];
+        let stats = run_with_writer(config, &mut out).unwrap();
+        assert_eq!(stats.exit_code(), EXIT_MATCH);
+        assert_eq!((stats.searches, stats.matched_lines, stats.errors), (1, 1, 0));
+        assert_eq!(String::from_utf8(out).unwrap(), "6:The sweeping up the heart,\n");
+
+        let config = Config::builder()
+            .pattern("needle")
+            .file("tests/inputs/bustle.txt")
             .build()
             .unwrap();
-        
-        // The two lines "Lorem" and "DOLOR" should match
-        let matches = find_lines(Cursor::new(&text), &re2, false);
-        assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
-
-        // When inverted, the one remaining line should match
-        let matches = find_lines(Cursor::new(&text), &re2, true);
-        assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
+        let mut out = vec![];
+        assert_eq!(run_with_writer(config, &mut out).unwrap().exit_code(), EXIT_NO_MATCH);
+        assert!(out.is_empty());
     }
 }
@@ -0,0 +1,688 @@
+// Printing what the search of each file found, in whichever of the output
+// formats the config asks for, and opening the files to search
+
+use crate::{
+    bench::Timed,
+    cancel::Cancellable,
+    decode, open,
+    progress::{Counted, ProgressHook},
+    BinaryFiles, Config, FileSummary, GreprError, MyResult, SearchStats, Sink,
+};
+use grepr_core::{search_lines, Match, SearchOptions};
+use memmap2::Mmap;
+use serde_json::json;
+use std::{
+    cell::Cell,
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
+};
+use tracing::{debug, debug_span, info};
+
+// Smaller files are cheaper to read than to map
+const MMAP_THRESHOLD: u64 = 1 << 20;
+// How much of a file is looked at for a NUL when deciding it is binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+// What searching one file came to, kept apart from its output so files
+// searched out of turn can be reported in order
+#[derive(Debug, Default)]
+pub(crate) struct Searched {
+    pub(crate) error: Option<String>,
+    pub(crate) has_match: bool,
+    pub(crate) printed: bool,
+    // Bytes of a separator written ahead of the file's first line
+    pub(crate) lead: usize,
+    pub(crate) stats: SearchStats,
+}
+
+impl Searched {
+    pub(crate) fn failed(error: String) -> Self {
+        Searched { error: Some(error), ..Searched::default() }
+    }
+}
+
+// Searches files and prints the results of each in the configured format
+pub(crate) struct Printer<'a> {
+    config: &'a Config,
+    options: SearchOptions,
+    is_single_file: bool,
+    has_context: bool,
+    list_files: bool,
+    line_terminator: char,
+    // Where relative paths are resolved for hyperlinks, and the host named in them
+    cwd: PathBuf,
+    host: String,
+    // Opens each file searched, `-` being standard input
+    pub(crate) open: Opener,
+}
+
+pub(crate) type Opener = fn(&str, Option<bool>) -> io::Result<Box<dyn BufRead>>;
+
+impl<'a> Printer<'a> {
+    pub(crate) fn new(config: &'a Config, is_single_file: bool) -> Self {
+        // Context lines are never printed when only the matched parts are shown,
+        // nor in the one-entry-per-match --vimgrep format
+        let (before_context, after_context) = if config.only_matching || config.vimgrep {
+            (0, 0)
+        } else {
+            (config.before_context, config.after_context)
+        };
+        let list_files = config.files_with_matches || config.files_without_match;
+        let line_terminator = if config.null_data { '\0' } else { '\n' };
+        Printer {
+            config,
+            options: SearchOptions {
+                invert_match: config.invert_match,
+                before_context,
+                after_context,
+                max_matches: if list_files || config.quiet {
+                    Some(config.max_count.map_or(1, |n| n.min(1)))
+                } else {
+                    config.max_count
+                },
+                line_terminator: line_terminator as u8,
+                multiline: config.multiline,
+                passthru: config.passthru && !config.only_matching && !config.vimgrep,
+            },
+            is_single_file,
+            has_context: before_context > 0 || after_context > 0,
+            list_files,
+            line_terminator,
+            cwd: env::current_dir().unwrap_or_default(),
+            host: match config.hyperlink_format {
+                Some(_) => sys_info::hostname().unwrap_or_default(),
+                None => String::new(),
+            },
+            open: open_searched,
+        }
+    }
+
+    // The file's name as printed, wrapped in an OSC 8 hyperlink when color
+    // is on and a format is given; standard input has nothing to link to
+    fn paint_filename(&self, filename: &str, position: Option<(usize, Option<usize>)>) -> String {
+        let config = self.config;
+        let name = display_name(filename, config.label.as_deref());
+        let name = paint(name, &config.colors.filename, config.color);
+        let format = match &config.hyperlink_format {
+            Some(format) if config.color && filename != "-" => format,
+            _ => return name,
+        };
+        // Collecting the components drops any ./ along the way
+        let path: PathBuf = self.cwd.join(filename).components().collect();
+        let mut encoded = String::new();
+        for &b in path.to_string_lossy().as_bytes() {
+            if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+                encoded.push(b as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+        // A position-less result such as a --count line links to the top
+        let (line, column) = position.unwrap_or((1, None));
+        let url = format
+            .replace("{path}", &encoded)
+            .replace("{host}", &self.host)
+            .replace("{line}", &line.to_string())
+            .replace("{column}", &column.unwrap_or(1).to_string());
+        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, name)
+    }
+
+    fn show_filename(&self, filename: &str) -> bool {
+        match self.config.with_filename {
+            Some(with_filename) => with_filename,
+            None => !self.is_single_file && (filename != "-" || self.config.label.is_some()),
+        }
+    }
+
+    // The line that sets a file's lines apart from whatever was printed
+    // before them: blank ahead of a heading, or else the group separator
+    // when there is context
+    pub(crate) fn lead(&self, filename: &str) -> Option<String> {
+        let config = self.config;
+        if config.quiet || self.list_files || config.count || config.json {
+            None
+        } else if config.heading && self.show_filename(filename) {
+            Some(String::new())
+        } else if self.has_context {
+            let separator = config.group_separator.as_ref()?;
+            Some(paint(separator, &config.colors.separator, config.color))
+        } else {
+            None
+        }
+    }
+
+    // Each line position is its number, its byte offset and, when asked for,
+    // the 1-based column of a match
+    fn print_prefix(
+        &self,
+        out: &mut dyn Write,
+        filename: &str,
+        line: Option<(usize, usize, Option<usize>)>,
+        sep: char,
+    ) -> io::Result<()> {
+        let config = self.config;
+        let sep = paint(&sep.to_string(), &config.colors.separator, config.color);
+        // Under --heading lines leave the name to the header above them
+        let show_filename = self.show_filename(filename) && !(config.heading && line.is_some());
+        if show_filename {
+            let name = self.paint_filename(filename, line.map(|(number, _, column)| (number, column)));
+            if config.null {
+                write!(out, "{}\0", name)?;
+            } else {
+                write!(out, "{}{}", name, sep)?;
+            }
+        }
+        if let Some((number, offset, column)) = line {
+            // Like GNU grep, -T also pads numbers so the tab lands consistently
+            let width = if config.initial_tab { 3 } else { 0 };
+            if config.line_number {
+                let number = format!("{:>1$}", number, width);
+                write!(out, "{}{}", paint(&number, &config.colors.line_number, config.color), sep)?;
+            }
+            if let Some(column) = column {
+                write!(out, "{}{}", paint(&column.to_string(), &config.colors.column, config.color), sep)?;
+            }
+            if config.byte_offset {
+                let offset = format!("{:>1$}", offset, width);
+                write!(out, "{}{}", paint(&offset, &config.colors.byte_offset, config.color), sep)?;
+            }
+            if config.initial_tab && (show_filename || config.line_number || config.byte_offset) {
+                write!(out, "\t")?;
+            }
+        }
+        Ok(())
+    }
+
+    // A whole line with its matches highlighted, unless it runs past
+    // --max-columns: then it is cut short, or left out altogether without
+    // --max-columns-preview
+    fn write_line(&self, out: &mut dyn Write, line: &Match, sgr: &str) -> io::Result<()> {
+        let config = self.config;
+        let terminator = self.line_terminator as u8;
+        let content = line.line.strip_suffix(&[terminator]).unwrap_or(&line.line);
+        let (text, marker) = match config.max_columns {
+            Some(max) if content.len() > max && config.max_columns_preview => {
+                // Cut on a character boundary so the preview stays valid UTF-8
+                let mut end = max;
+                while end > 0 && content[end] & 0xC0 == 0x80 {
+                    end -= 1;
+                }
+                let marker = format!(" [... {} more bytes]", content.len() - end);
+                (&content[..end], Some(marker))
+            }
+            Some(max) if content.len() > max => {
+                let matches = line.spans.iter().filter(|span| !span.is_empty()).count();
+                (&content[..0], Some(format!("[Omitted long line with {} matches]", matches)))
+            }
+            _ => (&line.line[..], None),
+        };
+        if config.color {
+            let spans: Vec<_> = line.spans.iter()
+                .map(|span| span.start.min(text.len())..span.end.min(text.len()))
+                .collect();
+            out.write_all(&highlight(text, &spans, sgr))?;
+        } else {
+            out.write_all(text)?;
+        }
+        if let Some(marker) = marker {
+            out.write_all(marker.as_bytes())?;
+            out.write_all(&line.line[content.len()..])?;
+        }
+        Ok(())
+    }
+
+    // Searches one file into `out` the way the command line prints it
+    pub(crate) fn print_file(&self, filename: &str, out: &mut dyn Write, printed_any: bool) -> MyResult<Searched> {
+        let mut sink = PrintSink {
+            printer: self,
+            out,
+            filename,
+            printed_any,
+            printed: false,
+            lead: 0,
+            last_number: None,
+        };
+        let searched = self.search_file(filename, &mut sink)?;
+        Ok(Searched {
+            printed: sink.printed,
+            lead: sink.lead,
+            ..searched
+        })
+    }
+
+    // Problems with the file itself end up in `error`; only a failure of the
+    // sink is returned as an error
+    pub(crate) fn search_file(&self, filename: &str, sink: &mut dyn Sink) -> MyResult<Searched> {
+        let config = self.config;
+        config.check_cancelled()?;
+        let _span = debug_span!("search", file = filename).entered();
+        // Files --pre-glob leaves out are read directly, as is standard input
+        let preprocessed = filename != "-"
+            && config.pre_glob.as_ref().is_none_or(|globs| {
+                let path = Path::new(filename);
+                globs.is_match(path.file_name().unwrap_or(path.as_os_str()))
+            });
+        let opening = Instant::now();
+        let file = match &config.pre {
+            Some(command) if preprocessed => {
+                debug!("preprocessing {} with {}", filename, command);
+                preprocess(command, filename)
+            }
+            _ => (self.open)(filename, config.mmap).map_err(From::from).and_then(|file| match config.search_zip {
+                true => decode::decode(decode::Kind::Compressed, filename, file),
+                false => Ok(file),
+            }),
+        };
+        let file = file.and_then(|file| decode::decode_text(config, filename, file));
+        if let Some(bench) = &config.bench {
+            bench.add_read(opening.elapsed());
+        }
+        let searched = match file {
+            Ok(file) => self.search_opened(filename, file, sink)?,
+            Err(e) => Err(e),
+        };
+        if let Some(ProgressHook(progress)) = &config.progress {
+            progress.file_completed(filename);
+        }
+        Ok(searched.unwrap_or_else(|e| Searched::failed(format!("{}: {}", filename, e))))
+    }
+
+    // Searches what was opened as `filename`, `-` for standard input. The
+    // outer error is the sink's and ends the search; the inner one is the
+    // file's own
+    pub(crate) fn search_opened(
+        &self,
+        filename: &str,
+        file: Box<dyn BufRead + '_>,
+        sink: &mut dyn Sink,
+    ) -> MyResult<MyResult<Searched>> {
+        let config = self.config;
+        let searching = Instant::now();
+        let reading = Cell::new(Duration::ZERO);
+        let file: Box<dyn BufRead + '_> = match &config.bench {
+            Some(_) => Box::new(Timed {
+                inner: file,
+                spent: &reading,
+            }),
+            None => file,
+        };
+        let file: Box<dyn BufRead + '_> = match &config.cancellation {
+            Some(token) => Box::new(Cancellable { inner: file, token }),
+            None => file,
+        };
+        let mut file: Box<dyn BufRead + '_> = match &config.progress {
+            Some(ProgressHook(progress)) => Box::new(Counted {
+                inner: file,
+                progress: &**progress,
+            }),
+            None => file,
+        };
+        // NUL is an ordinary record separator under -z, not a sign of binary data
+        let is_binary = config.binary_files != BinaryFiles::Text
+            && !config.null_data
+            && file.fill_buf().is_ok_and(|buf| buf[..buf.len().min(BINARY_SNIFF_LEN)].contains(&0));
+        let print_lines = !config.quiet && !self.list_files && !config.count;
+        let options = if is_binary && print_lines {
+            SearchOptions {
+                max_matches: Some(1),
+                before_context: 0,
+                after_context: 0,
+                ..self.options
+            }
+        } else {
+            self.options
+        };
+        sink.on_file_begin(filename)?;
+        // Lines go to the sink as they are found, so output keeps up with a
+        // slow stream
+        let path = (filename != "-").then(|| Path::new(filename));
+        let mut summary = FileSummary {
+            binary: is_binary,
+            ..FileSummary::default()
+        };
+        let mut sink_failed = false;
+        let result = if is_binary && config.binary_files == BinaryFiles::WithoutMatch {
+            // A skipped binary file is never read and simply counts as matchless
+            info!("skipping {}: binary file", filename);
+            Ok(0)
+        } else {
+            search_lines(file, &*config.pattern, &options, |line| {
+                if line.is_match {
+                    summary.has_match = true;
+                    summary.matched_lines += 1;
+                    summary.matches += line.spans.iter().filter(|span| !span.is_empty()).count();
+                }
+                // Only the summary tells of what a binary file holds
+                if is_binary {
+                    return Ok(());
+                }
+                let line = if config.trim {
+                    line.trim(self.line_terminator as u8)
+                } else {
+                    line
+                };
+                let is_match = line.is_match;
+                let found = Match::new(line, path);
+                let sent = if is_match {
+                    sink.on_match(&found)
+                } else {
+                    sink.on_context(&found)
+                };
+                sink_failed = sent.is_err();
+                Ok(sent?)
+            })
+        };
+        summary.bytes_searched = match result {
+            Err(e) if sink_failed => return Err(e),
+            Err(_) if config.check_cancelled().is_err() => return Err(GreprError::Cancelled),
+            Err(e) => return Ok(Err(e)),
+            Ok(bytes_read) => bytes_read,
+        };
+        if let Some(bench) = &config.bench {
+            bench.add_search(searching.elapsed(), reading.get());
+        }
+        debug!(
+            matched_lines = summary.matched_lines,
+            bytes_searched = summary.bytes_searched,
+            binary = summary.binary,
+            "searched {}",
+            filename
+        );
+        sink.on_file_end(filename, &summary)?;
+        Ok(Ok(Searched {
+            has_match: summary.has_match,
+            stats: SearchStats {
+                searches: 1,
+                searches_with_match: usize::from(summary.has_match),
+                matched_lines: summary.matched_lines,
+                matches: summary.matches,
+                bytes_searched: summary.bytes_searched,
+                ..SearchStats::default()
+            },
+            ..Searched::default()
+        }))
+    }
+}
+
+// The command line's own output for one file, written to `out` as the
+// search reports it
+struct PrintSink<'p, 'o> {
+    printer: &'p Printer<'p>,
+    out: &'o mut dyn Write,
+    filename: &'p str,
+    // Whether anything came before this file, and what it has printed since
+    printed_any: bool,
+    printed: bool,
+    lead: usize,
+    last_number: Option<usize>,
+}
+
+impl PrintSink<'_, '_> {
+    fn print_line(&mut self, line: &Match, is_match: bool) -> io::Result<()> {
+        let printer = self.printer;
+        let config = printer.config;
+        if config.quiet || printer.list_files || config.count {
+            return Ok(());
+        }
+        let (filename, out) = (self.filename, &mut *self.out);
+        if config.json {
+            let path = json!({ "text": display_name(filename, config.label.as_deref()) });
+            if self.last_number.is_none() {
+                writeln!(out, "{}", json!({ "type": "begin", "data": { "path": path } }))?;
+            }
+            self.last_number = Some(line.line_number);
+            let submatches: Vec<_> = line.spans.iter()
+                .filter(|span| !span.is_empty())
+                .map(|span| json!({
+                    "match": { "text": String::from_utf8_lossy(&line.line[span.clone()]) },
+                    "start": span.start,
+                    "end": span.end,
+                }))
+                .collect();
+            let event = json!({
+                "type": if is_match { "match" } else { "context" },
+                "data": {
+                    "path": path,
+                    "lines": { "text": String::from_utf8_lossy(&line.line) },
+                    "line_number": line.line_number,
+                    "absolute_offset": line.byte_offset,
+                    "submatches": submatches,
+                },
+            });
+            return writeln!(out, "{}", event);
+        }
+        let printed_any = self.printed_any || self.printed;
+        let heading = config.heading && printer.show_filename(filename);
+        if self.last_number.is_none() && printed_any {
+            if let Some(lead) = printer.lead(filename) {
+                writeln!(out, "{}", lead)?;
+                self.lead = lead.len() + 1;
+            }
+        }
+        if heading && self.last_number.is_none() {
+            writeln!(out, "{}", printer.paint_filename(filename, None))?;
+        } else if let Some(separator) = &config.group_separator {
+            // Groups of context within the file are set apart as well
+            if printer.has_context && self.last_number.is_some_and(|n| n + 1 != line.line_number) {
+                writeln!(out, "{}", paint(separator, &config.colors.separator, config.color))?;
+            }
+        }
+        self.last_number = Some(line.line_number);
+        self.printed = true;
+        let column = if config.column && is_match {
+            let first = line.spans.iter().find(|span| !span.is_empty());
+            Some(first.map_or(1, |span| span.start + 1))
+        } else {
+            None
+        };
+        let piece = |span: &Range<usize>| match &config.replace {
+            Some(template) => config.pattern.expand(&line.line, span, template),
+            None => line.line[span.clone()].to_vec(),
+        };
+        if config.vimgrep {
+            // One entry per match, each carrying the whole line
+            let mut columns: Vec<usize> = line.spans.iter()
+                .filter(|span| !span.is_empty())
+                .map(|span| span.start + 1)
+                .collect();
+            if columns.is_empty() {
+                columns.push(1);
+            }
+            for column in columns {
+                printer.print_prefix(out, filename, Some((line.line_number, line.byte_offset, Some(column))), ':')?;
+                printer.write_line(out, line, &config.colors.selected_match)?;
+            }
+        } else if config.only_matching {
+            for span in line.spans.iter().filter(|span| !span.is_empty()) {
+                let column = config.column.then_some(span.start + 1);
+                printer.print_prefix(out, filename, Some((line.line_number, line.byte_offset + span.start, column)), ':')?;
+                let text = piece(span);
+                if config.color {
+                    out.write_all(&paint_bytes(&text, &config.colors.selected_match))?;
+                } else {
+                    out.write_all(&text)?;
+                }
+                write!(out, "{}", printer.line_terminator)?;
+            }
+        } else if config.replace.is_some() && is_match {
+            printer.print_prefix(out, filename, Some((line.line_number, line.byte_offset, column)), ':')?;
+            let mut last = 0;
+            for span in line.spans.iter().filter(|span| !span.is_empty()) {
+                out.write_all(&line.line[last..span.start])?;
+                let text = piece(span);
+                if config.color {
+                    out.write_all(&paint_bytes(&text, &config.colors.selected_match))?;
+                } else {
+                    out.write_all(&text)?;
+                }
+                last = span.end;
+            }
+            out.write_all(&line.line[last..])?;
+        } else {
+            printer.print_prefix(
+                out,
+                filename,
+                Some((line.line_number, line.byte_offset, column)),
+                if is_match { ':' } else { '-' },
+            )?;
+            let sgr = if is_match {
+                &config.colors.selected_match
+            } else {
+                &config.colors.context_match
+            };
+            printer.write_line(out, line, sgr)?;
+        }
+        // Stdout only flushes by itself on a newline, which -z never writes
+        if config.line_buffered {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for PrintSink<'_, '_> {
+    fn on_match(&mut self, line: &Match) -> io::Result<()> {
+        self.print_line(line, true)
+    }
+
+    fn on_context(&mut self, line: &Match) -> io::Result<()> {
+        self.print_line(line, false)
+    }
+
+    fn on_file_end(&mut self, filename: &str, summary: &FileSummary) -> io::Result<()> {
+        let printer = self.printer;
+        let config = printer.config;
+        let out = &mut *self.out;
+        // An inverted match has no spans, so it counts as one line
+        let count = if config.count_matches && !config.invert_match {
+            summary.matches
+        } else {
+            summary.matched_lines
+        };
+        if config.json && self.last_number.is_some() {
+            let event = json!({
+                "type": "end",
+                "data": {
+                    "path": { "text": display_name(filename, config.label.as_deref()) },
+                    "stats": {
+                        "matched_lines": summary.matched_lines,
+                        "matches": summary.matches,
+                        "bytes_searched": summary.bytes_searched,
+                    },
+                },
+            });
+            writeln!(out, "{}", event)?;
+        }
+        if config.quiet {
+            // -q answers through the exit code alone
+        } else if printer.list_files {
+            if summary.has_match != config.files_without_match {
+                write!(
+                    out,
+                    "{}{}",
+                    printer.paint_filename(filename, None),
+                    if config.null { '\0' } else { '\n' },
+                )?;
+            }
+        } else if config.count {
+            printer.print_prefix(out, filename, None, ':')?;
+            writeln!(out, "{}", count)?;
+        } else if summary.binary && summary.has_match {
+            writeln!(
+                out,
+                "Binary file {} matches",
+                display_name(filename, config.label.as_deref()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn display_name<'a>(filename: &'a str, label: Option<&'a str>) -> &'a str {
+    match filename {
+        "-" => label.unwrap_or("(standard input)"),
+        _ => filename,
+    }
+}
+
+fn paint(s: &str, sgr: &str, color: bool) -> String {
+    // An empty capability leaves that part in the terminal's own colors
+    if color && !sgr.is_empty() {
+        format!("\x1b[{}m\x1b[K{}\x1b[m\x1b[K", sgr, s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn paint_bytes(s: &[u8], sgr: &str) -> Vec<u8> {
+    if sgr.is_empty() {
+        return s.to_vec();
+    }
+    let mut painted = format!("\x1b[{}m\x1b[K", sgr).into_bytes();
+    painted.extend_from_slice(s);
+    painted.extend_from_slice(b"\x1b[m\x1b[K");
+    painted
+}
+
+fn highlight(text: &[u8], spans: &[Range<usize>], sgr: &str) -> Vec<u8> {
+    let mut highlighted = Vec::with_capacity(text.len());
+    let mut last = 0;
+    for span in spans.iter().filter(|span| !span.is_empty()) {
+        highlighted.extend_from_slice(&text[last..span.start]);
+        highlighted.extend(paint_bytes(&text[span.clone()], sgr));
+        last = span.end;
+    }
+    highlighted.extend_from_slice(&text[last..]);
+    highlighted
+}
+
+// Regular files at least MMAP_THRESHOLD long are mapped into memory unless
+// --no-mmap is given, and --mmap maps any non-empty one, so the search takes
+// its lines straight from the page cache instead of through a read buffer
+pub(crate) fn open_searched(filename: &str, mmap: Option<bool>) -> io::Result<Box<dyn BufRead>> {
+    if filename == "-" || mmap == Some(false) {
+        return open(filename);
+    }
+    let file = File::open(filename)?;
+    let metadata = file.metadata()?;
+    let threshold = if mmap == Some(true) { 1 } else { MMAP_THRESHOLD };
+    if metadata.is_file() && metadata.len() >= threshold {
+        // SAFETY: the map is only ever read. A file truncated by another
+        // process mid-search can still fault, a risk every mmap-based grep
+        // accepts; --no-mmap avoids it
+        let map = unsafe { Mmap::map(&file)? };
+        return Ok(Box::new(Cursor::new(map)));
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// Like ripgrep, the command gets the path as its argument and the file's
+// contents on its stdin, so it can read whichever suits it
+fn preprocess(command: &str, filename: &str) -> MyResult<Box<dyn BufRead>> {
+    let output = process::Command::new(command)
+        .arg(filename)
+        .stdin(File::open(filename)?)
+        .output()
+        .map_err(|e| GreprError::Preprocessor {
+            command: command.to_string(),
+            reason: e.to_string(),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.trim() {
+            "" => output.status.to_string(),
+            stderr => stderr.to_string(),
+        };
+        return Err(GreprError::Preprocessor {
+            command: command.to_string(),
+            reason,
+        });
+    }
+    Ok(Box::new(Cursor::new(output.stdout)))
+}
+
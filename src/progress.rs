@@ -0,0 +1,116 @@
+// Telling an embedder, or a progress bar, how far a search has come
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    sync::Arc,
+};
+
+/// Hooks a search calls as it goes, set with
+/// [`ConfigBuilder::progress`](crate::ConfigBuilder::progress). Each has a
+/// default that does nothing, so an implementation picks what it shows.
+///
+/// A search with several threads calls them from all of them at once, and
+/// none should take long, since the search waits for it.
+pub trait Progress: Send + Sync {
+    /// The walk found `path` and it will be searched; files ruled out by
+    /// globs, types or an index never show up here.
+    fn file_discovered(&self, _path: &str) {}
+
+    /// `path` has been searched, or given up on because it could not be read.
+    fn file_completed(&self, _path: &str) {}
+
+    /// Another `bytes` of some file have been read; called many times a file.
+    fn bytes_scanned(&self, _bytes: usize) {}
+}
+
+#[derive(Clone)]
+pub(crate) struct ProgressHook(pub(crate) Arc<dyn Progress>);
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+// Counts what is read of `inner` as it is consumed, a line or so at a time
+pub(crate) struct Counted<'p, R> {
+    pub(crate) inner: R,
+    pub(crate) progress: &'p dyn Progress,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.bytes_scanned(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.progress.bytes_scanned(amt);
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progress;
+    use crate::{search_with, Config};
+    use std::{
+        ops::ControlFlow,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+        bytes: Mutex<usize>,
+    }
+
+    impl Progress for Recorder {
+        fn file_discovered(&self, path: &str) {
+            self.events.lock().unwrap().push(format!("found {}", path));
+        }
+
+        fn file_completed(&self, path: &str) {
+            self.events.lock().unwrap().push(format!("done {}", path));
+        }
+
+        fn bytes_scanned(&self, bytes: usize) {
+            *self.bytes.lock().unwrap() += bytes;
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        let recorder = Arc::new(Recorder::default());
+        let config = Config::builder()
+            .pattern("The")
+            .file("tests/inputs/bustle.txt")
+            .file("no/such/file")
+            .file("tests/inputs/fox.txt")
+            .threads(1)
+            .progress(recorder.clone())
+            .build()
+            .unwrap();
+        let stats = search_with(&config, |_| ControlFlow::Continue(())).unwrap();
+
+        // A file the walk cannot even find is an error, not a discovery
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "found tests/inputs/bustle.txt",
+                "done tests/inputs/bustle.txt",
+                "found tests/inputs/fox.txt",
+                "done tests/inputs/fox.txt",
+            ]
+        );
+        assert_eq!(*recorder.bytes.lock().unwrap(), stats.bytes_searched);
+    }
+}
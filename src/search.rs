@@ -0,0 +1,495 @@
+// The search driver: files from the walk are handed to a printer one after
+// another, or to worker threads whose output is written back in walk order,
+// and the totals are added up as each file is done
+
+use crate::{
+    decode, index,
+    printer::{display_name, open_searched, Opener, Printer, Searched},
+    progress::ProgressHook,
+    sink::FnSink,
+    Config, GreprError, MyResult, SearchStats, Sink,
+};
+use grepr_core::{walk_files, Match};
+use serde_json::json;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::ControlFlow,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+use tracing::{debug, info};
+
+// The search itself, once nothing else was asked for
+pub(crate) fn search_to<W: Write>(config: &Config, out: &mut W) -> MyResult<SearchStats> {
+    let started = Instant::now();
+    let mut stats = SearchStats {
+        quiet: config.quiet,
+        ..SearchStats::default()
+    };
+    debug!(
+        "matching with {} on {} thread{}",
+        config.pattern.name(),
+        config.threads,
+        if config.threads == 1 { "" } else { "s" }
+    );
+    let (entries, is_single_file) = candidates(config);
+    let printer = Printer::new(config, is_single_file);
+    let mut errors = 0;
+    let mut warn = |msg: String| {
+        errors += 1;
+        if !config.no_messages {
+            eprintln!("{}", msg);
+        }
+    };
+    let mut printed_any = false;
+    let mut matched_any = false;
+    if config.list_candidates {
+        for entry in entries {
+            config.check_cancelled()?;
+            match entry {
+                Err(e) => warn(e.to_string()),
+                Ok(filename) => {
+                    matched_any = true;
+                    write!(
+                        out,
+                        "{}{}",
+                        display_name(&filename, config.label.as_deref()),
+                        if config.null { '\0' } else { '\n' },
+                    )?;
+                }
+            }
+        }
+    } else if config.threads <= 1 || is_single_file || config.line_buffered {
+        // A lone file, and any --line-buffered search, streams straight out
+        for entry in entries {
+            let searched = match entry {
+                Err(e) => Searched::failed(e.to_string()),
+                Ok(filename) => printer.print_file(&filename, out, printed_any)?,
+            };
+            if let Some(msg) = searched.error {
+                warn(msg);
+                continue;
+            }
+            printed_any |= searched.printed;
+            matched_any |= searched.has_match;
+            stats.add(&searched.stats);
+            if config.quiet && searched.has_match {
+                break;
+            }
+        }
+    } else {
+        // The walk runs on threads of its own, and files are numbered in the
+        // order it finds them and handed to the workers, so a big tree is
+        // searched while still being walked. Each worker holds a file's output
+        // until its number comes up to be written, or until the output
+        // outgrows SPILL_LEN, when it waits for the turn and streams the rest;
+        // nor does one get more than MAX_AHEAD files ahead.
+        let turns = Turns::default();
+        thread::scope(|scope| -> MyResult<()> {
+            let (found, taken) = mpsc::sync_channel(config.threads);
+            scope.spawn(move || {
+                for entry in entries.enumerate() {
+                    if found.send(entry).is_err() {
+                        break;
+                    }
+                }
+            });
+            // Dropped with the last worker, which ends the walk if it stopped early
+            let taken = Arc::new(Mutex::new(taken));
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..config.threads {
+                let (sender, taken) = (sender.clone(), taken.clone());
+                let (printer, turns) = (&printer, &turns);
+                scope.spawn(move || loop {
+                    let Ok((index, entry)) = taken.lock().unwrap().recv() else {
+                        break;
+                    };
+                    if !turns.wait_for(index.saturating_sub(MAX_AHEAD)) {
+                        break;
+                    }
+                    let mut output = Spill {
+                        index,
+                        buf: vec![],
+                        lead: 0,
+                        streaming: false,
+                        sender: &sender,
+                        turns,
+                    };
+                    let searched = match entry {
+                        Err(e) => Ok(Searched::failed(e.to_string())),
+                        Ok(filename) => {
+                            output.lead = printer.lead(&filename).map_or(0, |lead| lead.len() + 1);
+                            printer.print_file(&filename, &mut output, true)
+                        }
+                    };
+                    let searched = searched.map(|searched| (output.buf, searched));
+                    if sender.send((index, Piece::Done(searched))).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop((sender, taken));
+            let mut pending = HashMap::new();
+            let mut turn = 0;
+            let mut streamed = false;
+            // Stop the workers early on an error or once -q has its answer
+            let stop = |result| {
+                turns.stop();
+                result
+            };
+            for (index, piece) in receiver {
+                match piece {
+                    // Only the file whose turn it is streams
+                    Piece::Output { bytes, lead } => {
+                        let skip = if printed_any { 0 } else { lead };
+                        if let Err(e) = out.write_all(&bytes[skip..]) {
+                            return stop(Err(GreprError::from(e)));
+                        }
+                        printed_any = true;
+                        streamed = true;
+                    }
+                    Piece::Done(searched) => {
+                        pending.insert(index, searched);
+                    }
+                }
+                while let Some(searched) = pending.remove(&turn) {
+                    turn += 1;
+                    turns.advance(turn);
+                    let streamed = std::mem::take(&mut streamed);
+                    let (output, searched) = match searched {
+                        Err(e) => return stop(Err(e)),
+                        Ok(searched) => searched,
+                    };
+                    if let Some(msg) = searched.error {
+                        warn(msg);
+                        continue;
+                    }
+                    // Only keep the separator ahead of the file if something
+                    // was printed before it
+                    let skip = if printed_any || streamed { 0 } else { searched.lead };
+                    if let Err(e) = out.write_all(&output[skip..]) {
+                        return stop(Err(GreprError::from(e)));
+                    }
+                    printed_any |= searched.printed;
+                    matched_any |= searched.has_match;
+                    stats.add(&searched.stats);
+                    if config.quiet && searched.has_match {
+                        return stop(Ok(()));
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    }
+    stats.errors = errors;
+    stats.matched = matched_any;
+    stats.elapsed = started.elapsed();
+    info!(
+        searches = stats.searches,
+        matched_lines = stats.matched_lines,
+        bytes_searched = stats.bytes_searched,
+        errors = stats.errors,
+        "search finished in {:?}",
+        stats.elapsed
+    );
+    if config.json {
+        let elapsed = stats.elapsed;
+        let event = json!({
+            "type": "summary",
+            "data": {
+                "elapsed_total": {
+                    "secs": elapsed.as_secs(),
+                    "nanos": elapsed.subsec_nanos(),
+                    "human": format!("{:.6}s", elapsed.as_secs_f64()),
+                },
+                "stats": {
+                    "searches": stats.searches,
+                    "searches_with_match": stats.searches_with_match,
+                    "matched_lines": stats.matched_lines,
+                    "matches": stats.matches,
+                    "bytes_searched": stats.bytes_searched,
+                },
+            },
+        });
+        writeln!(out, "{}", event)?;
+    } else if config.stats {
+        let elapsed = stats.elapsed;
+        writeln!(out)?;
+        writeln!(out, "{} matches", stats.matches)?;
+        writeln!(out, "{} matched lines", stats.matched_lines)?;
+        writeln!(out, "{} files contained matches", stats.searches_with_match)?;
+        writeln!(out, "{} files searched", stats.searches)?;
+        writeln!(out, "{} bytes searched", stats.bytes_searched)?;
+        writeln!(out, "{:.6} seconds", elapsed.as_secs_f64())?;
+    }
+    Ok(stats)
+}
+
+/// Searches as configured, one file after another, handing everything found
+/// to `sink` instead of printing it. Returns the totals as `run` does.
+pub fn run_with_sink(config: &Config, sink: &mut dyn Sink) -> MyResult<SearchStats> {
+    search_files(config, sink, &Cell::new(false), open_searched)
+}
+
+/// Searches as configured, one file after another, calling `on_match` with
+/// each selected line as soon as it is found. Returning `ControlFlow::Break`
+/// ends the search there. Files that cannot be searched are passed over and
+/// only counted in the totals returned.
+pub fn search_with<F>(config: &Config, on_match: F) -> MyResult<SearchStats>
+where
+    F: FnMut(Match) -> ControlFlow<()>,
+{
+    let stopped = Cell::new(false);
+    let mut sink = FnSink {
+        on_match,
+        stopped: &stopped,
+    };
+    search_files(config, &mut sink, &stopped, open_searched)
+}
+
+/// Searches whatever `reader` holds as if it were a file given on the
+/// command line, and returns the selected lines in order, without a path.
+/// The files and everything about finding them are left out of it, as is
+/// `--pre`, which needs a file to run on.
+pub fn search_reader<R: BufRead>(config: &Config, reader: R) -> MyResult<Vec<Match>> {
+    let mut found = vec![];
+    let stopped = Cell::new(false);
+    let mut sink = FnSink {
+        on_match: |line| {
+            found.push(line);
+            ControlFlow::Continue(())
+        },
+        stopped: &stopped,
+    };
+    let printer = Printer::new(config, true);
+    let mut reader: Box<dyn BufRead + '_> = Box::new(reader);
+    if config.search_zip {
+        reader = decode::decode(decode::Kind::Compressed, "", reader)?;
+    }
+    let reader = decode::decode_text(config, "", reader)?;
+    printer.search_opened("-", reader, &mut sink)??;
+    Ok(found)
+}
+
+/// Searches `bytes` as [`search_reader`] does.
+pub fn search_slice(config: &Config, bytes: &[u8]) -> MyResult<Vec<Match>> {
+    search_reader(config, bytes)
+}
+
+// A sink error ends the search with that error, unless `stopped` has been
+// set, which means the sink asked to stop and the search simply ends
+pub(crate) fn search_files(
+    config: &Config,
+    sink: &mut dyn Sink,
+    stopped: &Cell<bool>,
+    open: Opener,
+) -> MyResult<SearchStats> {
+    let started = Instant::now();
+    let (entries, is_single_file) = candidates(config);
+    let mut printer = Printer::new(config, is_single_file);
+    printer.open = open;
+    let mut stats = SearchStats {
+        quiet: config.quiet,
+        ..SearchStats::default()
+    };
+    for entry in entries {
+        let searched = match entry.map(|filename| printer.search_file(&filename, sink)) {
+            Err(e) => Searched::failed(e.to_string()),
+            Ok(Err(_)) if stopped.get() => {
+                stats.matched = true;
+                break;
+            }
+            Ok(searched) => searched?,
+        };
+        if let Some(msg) = searched.error {
+            stats.errors += 1;
+            sink.on_error(&msg);
+            continue;
+        }
+        stats.matched |= searched.has_match;
+        stats.add(&searched.stats);
+        if config.quiet && searched.has_match {
+            break;
+        }
+    }
+    stats.elapsed = started.elapsed();
+    Ok(stats)
+}
+
+// The files to search as the walk finds them, and whether just one was found
+// before the index ruled any out, as file names show as they would have
+// without it. Only the first two are looked for before returning.
+fn candidates(config: &Config) -> (Box<dyn Iterator<Item = MyResult<String>> + Send + '_>, bool) {
+    let files = walk_files(&config.files, config.directories, &config.walk_options);
+    let mut files: Box<dyn Iterator<Item = MyResult<String>> + Send> = match &config.bench {
+        Some(bench) => Box::new(bench.timed_walk(files)),
+        None => files,
+    };
+    let first: Vec<_> = files.by_ref().take(2).collect();
+    let is_single_file = first.len() == 1;
+    let entries = first.into_iter().chain(files);
+    let entries: Box<dyn Iterator<Item = MyResult<String>> + Send> =
+        match config.index_query.as_ref().filter(|_| !config.list_candidates) {
+            Some(query) => {
+                let mut filter = index::Filter::new(query);
+                Box::new(entries.filter(move |entry| match entry {
+                    Ok(filename) => {
+                        let may_match = filter.may_match(filename);
+                        if !may_match {
+                            info!("skipping {}: the index rules it out", filename);
+                        }
+                        may_match
+                    }
+                    Err(_) => true,
+                }))
+            }
+            None => Box::new(entries),
+        };
+    match &config.progress {
+        Some(ProgressHook(progress)) => {
+            let entries = entries.inspect(move |entry| {
+                if let Ok(filename) = entry {
+                    progress.file_discovered(filename);
+                }
+            });
+            (Box::new(entries), is_single_file)
+        }
+        None => (entries, is_single_file),
+    }
+}
+
+// How much of a file's output a worker holds while files before it are still
+// being written, and how far past those it may go on to other files
+const SPILL_LEN: usize = 64 * 1024;
+const MAX_AHEAD: usize = 256;
+
+// What a worker sends of the file it was given: output to be written at once,
+// sent only when the file's turn has come, then how the search went along
+// with whatever output is left
+enum Piece {
+    Output { bytes: Vec<u8>, lead: usize },
+    Done(MyResult<(Vec<u8>, Searched)>),
+}
+
+// Which file's output is written next, for workers to wait on
+#[derive(Default)]
+struct Turns {
+    // Also set once the search has stopped early
+    next: Mutex<(usize, bool)>,
+    changed: Condvar,
+}
+
+impl Turns {
+    // Whether `index` came up before the search was stopped
+    fn wait_for(&self, index: usize) -> bool {
+        let mut next = self.next.lock().unwrap();
+        while next.0 < index && !next.1 {
+            next = self.changed.wait(next).unwrap();
+        }
+        !next.1
+    }
+
+    fn advance(&self, turn: usize) {
+        self.next.lock().unwrap().0 = turn;
+        self.changed.notify_all();
+    }
+
+    fn stop(&self) {
+        self.next.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+}
+
+// A worker's output for its file, held until it outgrows SPILL_LEN
+struct Spill<'s> {
+    index: usize,
+    buf: Vec<u8>,
+    // Bytes of a separator at the start of `buf`, until it is first sent
+    lead: usize,
+    streaming: bool,
+    sender: &'s mpsc::Sender<(usize, Piece)>,
+    turns: &'s Turns,
+}
+
+impl Write for Spill<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= SPILL_LEN {
+            let stopped = || io::Error::other("search stopped");
+            if !self.streaming && !self.turns.wait_for(self.index) {
+                return Err(stopped());
+            }
+            self.streaming = true;
+            let piece = Piece::Output {
+                bytes: std::mem::take(&mut self.buf),
+                lead: std::mem::take(&mut self.lead),
+            };
+            self.sender.send((self.index, piece)).map_err(|_| stopped())?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_reader, search_slice, search_with};
+    use crate::{Config, EXIT_MATCH};
+    use std::{io, ops::ControlFlow};
+
+    #[test]
+    fn test_search_with() {
+        let config = Config::builder()
+            .pattern("The")
+            .file("tests/inputs/bustle.txt")
+            .file("tests/inputs/fox.txt")
+            .build()
+            .unwrap();
+        let mut found = vec![];
+        let stats = search_with(&config, |line| {
+            found.push((line.path.unwrap(), line.line_number));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[3].0.to_str(), Some("tests/inputs/fox.txt"));
+        assert_eq!((stats.searches, stats.matched_lines), (2, 4));
+
+        // Breaking off leaves the remaining lines and files unsearched
+        let mut found = vec![];
+        let stats = search_with(&config, |line| {
+            found.push(line.line_number);
+            match found.len() {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .unwrap();
+        assert_eq!(found, [1, 2]);
+        assert_eq!(stats.exit_code(), EXIT_MATCH);
+    }
+
+    #[test]
+    fn test_search_slice() {
+        let config = Config::builder().pattern("fox").case_insensitive(true).build().unwrap();
+        let found = search_slice(&config, b"Fox one\ntwo\nthree fox\n").unwrap();
+        let lines: Vec<_> = found.iter().map(|line| (line.line_number, line.line.as_slice())).collect();
+        assert_eq!(lines, [(1, &b"Fox one\n"[..]), (3, &b"three fox\n"[..])]);
+        assert!(found.iter().all(|line| line.path.is_none()));
+
+        // A byte order mark is followed as it is in a file
+        let found = search_slice(&config, b"\xFF\xFEf\x00o\x00x\x00\n\x00").unwrap();
+        assert_eq!(found[0].line, b"fox\n");
+
+        let config = Config::builder().pattern("fox").invert_match(true).build().unwrap();
+        let found = search_reader(&config, io::Cursor::new("fox\ndog\n")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line_number, 2);
+    }
+}
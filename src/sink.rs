@@ -0,0 +1,118 @@
+// Where a search reports what it finds, so output formats other than the
+// command line's own can be plugged in
+
+use grepr_core::Match;
+use std::{cell::Cell, io, ops::ControlFlow};
+
+/// Receives the results of a search one file at a time: `on_file_begin`,
+/// then every selected and context line in order, then `on_file_end`. A
+/// file that cannot be read to the end gets `on_error` instead of
+/// `on_file_end`. Paths are as named, `-` standing for standard input.
+///
+/// An error returned from any method stops the search.
+pub trait Sink {
+    fn on_file_begin(&mut self, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_match(&mut self, line: &Match) -> io::Result<()>;
+
+    fn on_context(&mut self, _line: &Match) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_file_end(&mut self, _path: &str, _summary: &FileSummary) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_error(&mut self, _message: &str) {}
+}
+
+/// How the search of one file went. The lines of a binary file are never
+/// handed to the sink, so this is all that is told of one.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileSummary {
+    pub has_match: bool,
+    pub binary: bool,
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub bytes_searched: usize,
+}
+
+// Hands `search_with`'s callback each selected line, failing once it says to
+// stop so the search gives up at once
+pub(crate) struct FnSink<'s, F> {
+    pub(crate) on_match: F,
+    pub(crate) stopped: &'s Cell<bool>,
+}
+
+impl<F: FnMut(Match) -> ControlFlow<()>> Sink for FnSink<'_, F> {
+    fn on_match(&mut self, line: &Match) -> io::Result<()> {
+        match (self.on_match)(line.clone()) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => {
+                self.stopped.set(true);
+                Err(io::Error::new(io::ErrorKind::Interrupted, "search stopped"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSummary, Sink};
+    use crate::{run_with_sink, Config, EXIT_ERROR};
+    use grepr_core::Match;
+    use std::io;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Sink for Events {
+        fn on_file_begin(&mut self, path: &str) -> io::Result<()> {
+            self.0.push(format!("begin {}", path));
+            Ok(())
+        }
+
+        fn on_match(&mut self, line: &Match) -> io::Result<()> {
+            self.0.push(format!("match {}", line.line_number));
+            Ok(())
+        }
+
+        fn on_context(&mut self, line: &Match) -> io::Result<()> {
+            self.0.push(format!("context {}", line.line_number));
+            Ok(())
+        }
+
+        fn on_file_end(&mut self, path: &str, summary: &FileSummary) -> io::Result<()> {
+            self.0.push(format!("end {} {}", path, summary.matched_lines));
+            Ok(())
+        }
+
+        fn on_error(&mut self, message: &str) {
+            self.0.push(format!("error {}", message));
+        }
+    }
+
+    #[test]
+    fn test_run_with_sink() {
+        let config = Config::builder()
+            .pattern("sweeping")
+            .file("tests/inputs/bustle.txt")
+            .file("no/such/file")
+            .context(0, 1)
+            .build()
+            .unwrap();
+        let mut events = Events::default();
+        let stats = run_with_sink(&config, &mut events).unwrap();
+        assert_eq!(stats.exit_code(), EXIT_ERROR);
+        assert_eq!((stats.searches, stats.matched_lines, stats.errors), (1, 1, 1));
+        assert_eq!(events.0[..4], [
+            "begin tests/inputs/bustle.txt",
+            "match 6",
+            "context 7",
+            "end tests/inputs/bustle.txt 1",
+        ]);
+        assert!(events.0[4].starts_with("error no/such/file: "));
+    }
+}
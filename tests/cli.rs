@@ -1,7 +1,10 @@
+// The original tests pass arrays by reference, which newer clippy flags
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::{fs, path::Path};
+use std::{fs, io::Write, path::Path};
 use sys_info::os_type;
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
@@ -34,7 +37,7 @@ fn dies_no_args() -> TestResult {
     Command::cargo_bin(PRG)?
         .assert()
         .failure()
-        .stderr(predicate::str::contains("USAGE"));
+        .stderr(predicate::str::contains("Usage:"));
     Ok(())
 }
 
@@ -277,3 +280,1931 @@ fn stdin_insensitive_count() -> TestResult {
         .stdout(expected);
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number() -> TestResult {
+    run(
+        &["-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_line_number() -> TestResult {
+    run(
+        &["--line-number", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn after_context() -> TestResult {
+    run(
+        &["-A", "1", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn after_context_line_number() -> TestResult {
+    run(
+        &["-n", "--after-context", "1", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.after_context.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-A", "x", "The", BUSTLE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid context length \"x\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn before_context_line_number() -> TestResult {
+    run(
+        &["-n", "-B", "1", "The", BUSTLE, NOBODY],
+        "tests/expected/all.the.capitalized.before_context.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn before_and_after_context() -> TestResult {
+    run(
+        &["--before-context", "2", "--after-context", "1", "morning", BUSTLE],
+        "tests/expected/bustle.txt.morning.before_after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn context_group_separator() -> TestResult {
+    run(
+        &["-C", "1", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn context_shorthand() -> TestResult {
+    run(
+        &["-n", "-1", "-A", "0", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.context.shorthand",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    run(
+        &["--color=always", "-n", "the", BUSTLE, FOX],
+        "tests/expected/all.the.lowercase.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_never() -> TestResult {
+    run(
+        &["--color=never", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> TestResult {
+    run(
+        &["-l", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.files_with_matches",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files-with-matches", "fox"])
+        .write_stdin(fs::read_to_string(FOX)?)
+        .assert()
+        .stdout("(standard input)\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> TestResult {
+    run(
+        &["-L", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.files_without_match",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_matching() -> TestResult {
+    run(
+        &["-o", "-n", "-i", "the\\w*", BUSTLE, NOBODY],
+        "tests/expected/all.the.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp() -> TestResult {
+    run(
+        &["-w", "-i", "the", BUSTLE, NOBODY, FOX],
+        "tests/expected/all.the.word_regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_regexp() -> TestResult {
+    run(
+        &["-x", "-n", "The.*", BUSTLE, FOX],
+        "tests/expected/all.the.line_regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_regexp_invert_count() -> TestResult {
+    run(
+        &["--line-regexp", "-v", "-c", "The.*", BUSTLE],
+        "tests/expected/bustle.txt.the.line_regexp.invert.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings() -> TestResult {
+    run(&["-F", "you?", NOBODY], "tests/expected/nobody.txt.fixed_strings")
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings_insensitive() -> TestResult {
+    run(
+        &["--fixed-strings", "-i", "NOBODY!", NOBODY],
+        "tests/expected/nobody.txt.fixed_strings",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_regexps() -> TestResult {
+    run(
+        &["-e", "The", "--regexp", "dog", BUSTLE, FOX],
+        "tests/expected/all.the.dog.regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regexp_frees_first_positional() -> TestResult {
+    run(
+        &["-e", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file() -> TestResult {
+    run(
+        &["-f", "tests/patterns/dog_bustle.txt", "-e", "morning", BUSTLE, FOX],
+        "tests/expected/all.pattern_file",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file_stdin_blank_line() -> TestResult {
+    // A blank line is an empty pattern, which matches every line
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-", BUSTLE])
+        .write_stdin("zzz\n\n")
+        .assert()
+        .stdout(fs::read_to_string(BUSTLE)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_pattern_file() -> TestResult {
+    run(&["-f", EMPTY, FOX], "tests/expected/empty.foo")
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count() -> TestResult {
+    run(
+        &["-m", "2", "-A", "1", "-n", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.max_count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_trailing_context() -> TestResult {
+    // Matches past the limit still show as trailing context, as in GNU grep
+    Command::cargo_bin(PRG)?
+        .args(["-m", "1", "-A", "1", "-n", "The", BUSTLE])
+        .assert()
+        .stdout("1:The bustle in a house\n2-The morning after death\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_with_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--max-count", "2", "-c", "The", BUSTLE])
+        .assert()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "The", EMPTY, BUSTLE])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "zzz", BUSTLE])
+        .assert()
+        .failure()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_offset() -> TestResult {
+    run(
+        &["-b", "-n", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.byte_offset",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_offset_only_matching() -> TestResult {
+    run(
+        &["--byte-offset", "-o", "the", BUSTLE, FOX],
+        "tests/expected/all.the.lowercase.byte_offset.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn with_filename() -> TestResult {
+    run(
+        &["-H", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.with_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_filename() -> TestResult {
+    run(
+        &["--no-filename", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.no_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_label() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/all.the.capitalized.label")?;
+    Command::cargo_bin(PRG)?
+        .args(["--label=stdin-logs", "The", BUSTLE, "-"])
+        .write_stdin(fs::read_to_string(FOX)?)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_files_with_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-lZ", "The", BUSTLE, EMPTY, FOX])
+        .assert()
+        .stdout(format!("{}\0{}\0", BUSTLE, FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_filename_prefix() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null", "-n", "dog", BUSTLE, FOX])
+        .assert()
+        .stdout(format!(
+            "{}\01:The quick brown fox jumps over the lazy dog.\n",
+            FOX,
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "foo"])
+        .write_stdin("foo\nbar\0baz\0qux foo\0")
+        .assert()
+        .stdout("foo\nbar\0qux foo\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data_only_matching() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-o", "fo+"])
+        .write_stdin("foo bar\0baz\0qux fooo\0")
+        .assert()
+        .stdout("foo\0fooo\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("foo")
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .success()
+        .stdout("Binary file (standard input) matches\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_as_text() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-a", "o"])
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .success()
+        .stdout("hello\0world\nfoo\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_binary() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-I", "foo"])
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .failure()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_skips_binary() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_skips_binary");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("binary.dat"), "hello\0world\nfoo\n")?;
+    fs::write(dir.join("text.txt"), "foo\n")?;
+    let text = dir.join("text.txt").to_string_lossy().into_owned();
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "foo", &dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(format!("{}:foo\n", text));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_messages() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-s", "fox", &bad, INPUTS_DIR, FOX])
+        .assert()
+        .stderr("")
+        .stdout(format!("{}:The quick brown fox jumps over the lazy dog.\n", FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_files_types() -> TestResult {
+    let input = "hello\0world\nfoo\n";
+    for (binary_files, stdout) in [
+        ("binary", "Binary file (standard input) matches\n"),
+        ("text", "foo\n"),
+        ("without-match", ""),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args([&format!("--binary-files={}", binary_files), "foo"])
+            .write_stdin(input)
+            .assert()
+            .stdout(stdout);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_files_last_flag_wins() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-I", "--binary-files=text", "foo"])
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .stdout("foo\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn directories_skip() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", "skip", "fox", INPUTS_DIR, FOX])
+        .assert()
+        .success()
+        .stderr("")
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn directories_recurse() -> TestResult {
+    run(
+        &["--directories=recurse", "dog", INPUTS_DIR],
+        "tests/expected/dog.recursive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_codes() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?.args(["fox", FOX]).assert().code(0);
+    Command::cargo_bin(PRG)?.args(["zzz", FOX]).assert().code(1);
+    Command::cargo_bin(PRG)?.args(["*foo", FOX]).assert().code(2);
+    Command::cargo_bin(PRG)?.args(["fox", &bad, FOX]).assert().code(2);
+    Command::cargo_bin(PRG)?.args(["-s", "fox", &bad, FOX]).assert().code(2);
+    // A pattern file that cannot be read stops the search before it starts
+    Command::cargo_bin(PRG)?.args(["-f", &bad, FOX]).assert().code(3);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_code_quiet_ignores_errors() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", &bad, FOX])
+        .assert()
+        .code(0);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn custom_group_separator() -> TestResult {
+    run(
+        &["-C", "1", "--group-separator=~~", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.context.group_separator",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_group_separator() -> TestResult {
+    run(
+        &["-C", "1", "--no-group-separator", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.context.no_group_separator",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn initial_tab() -> TestResult {
+    run(
+        &["-T", "-n", "The", BUSTLE, FOX],
+        "tests/expected/all.the.capitalized.initial_tab",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "fancy-regex")]
+fn pcre_backreference() -> TestResult {
+    run(
+        &["-P", "(\\w)\\1", BUSTLE],
+        "tests/expected/bustle.txt.pcre.backreference",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "fancy-regex")]
+fn lookaround_falls_back() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "The (?!bustle)", BUSTLE])
+        .assert()
+        .success()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(feature = "hyperscan"))]
+fn engine_hyperscan_unavailable() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "hyperscan", "dog", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr("grepr was built without hyperscan support\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "hyperscan")]
+fn engine_hyperscan_unicode() -> TestResult {
+    // Classes cover whole characters unless --no-unicode asks for bytes
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "hyperscan", "-c", r"you\W\W\WNobody", NOBODY])
+        .assert()
+        .failure()
+        .stdout("0\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "hyperscan", "--no-unicode", "-c", r"you\W\W\WNobody", NOBODY])
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn engine_conflicts_with_pcre() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "default", "-P", "dog", BUSTLE])
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-ir", "--include=f*", "--include", "b*", "the", "tests/inputs"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("fox.txt"))
+        .stdout(predicates::str::contains("bustle.txt"))
+        .stdout(predicates::str::contains("nobody.txt").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn include_bad_glob() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--include=a[", "the", "tests/inputs"])
+        .assert()
+        .code(2)
+        .stderr("Invalid glob \"a[\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-ir", "--exclude=n*", "--exclude", "*.rs", "the", "tests/inputs", "tests/cli.rs"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("fox.txt"))
+        .stdout(predicates::str::contains("nobody.txt").not())
+        .stdout(predicates::str::contains("cli.rs").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude_dir() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--exclude-dir=expected", "--exclude-dir", "pat*", "the", "tests"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("tests/inputs/fox.txt"))
+        .stdout(predicates::str::contains("cli.rs"))
+        .stdout(predicates::str::contains("expected").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude_from() -> TestResult {
+    let expected = "tests/inputs/bustle.txt\ntests/inputs/fox.txt\n";
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rli", "--exclude-from", "tests/patterns/excludes.txt", "the", "tests"])
+        .output()?;
+    assert!(output.status.success());
+    let mut files: Vec<_> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| line.replace('\\', "/") + "\n")
+        .collect();
+    files.sort();
+    assert_eq!(files.concat(), expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn dereference_recursive() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dereference_recursive");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join("sub/text.txt"), "foo\n")?;
+    std::os::unix::fs::symlink(Path::new(FOX).canonicalize()?, dir.join("fox.txt"))?;
+    std::os::unix::fs::symlink("..", dir.join("sub/loop"))?;
+    let dir = dir.to_string_lossy();
+
+    // Symbolic links are skipped unless asked for
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "foo|fox", &dir])
+        .assert()
+        .success()
+        .stdout(format!("{}/sub/text.txt\n", dir));
+
+    // Following them finds the linked file and reports the cycle
+    for flags in [&["-R"][..], &["-r", "--follow"]] {
+        let output = Command::cargo_bin(PRG)?
+            .args(flags)
+            .args(["-l", "foo|fox", &dir])
+            .output()?;
+        assert_eq!(output.status.code(), Some(2));
+        let mut files: Vec<_> = String::from_utf8(output.stdout)?.lines().map(String::from).collect();
+        files.sort();
+        assert_eq!(files, [format!("{}/fox.txt", dir), format!("{}/sub/text.txt", dir)]);
+        assert!(String::from_utf8(output.stderr)?.contains("loop"));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn line_buffered_streams() -> TestResult {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+
+    // Each NUL-terminated record must come out before stdin is closed
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-z", "--line-buffered", "fox"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut record = vec![];
+    for text in ["a fox\0", "no dog\0", "two foxes\0"] {
+        stdin.write_all(text.as_bytes())?;
+        stdin.flush()?;
+        if text.contains("fox") {
+            record.clear();
+            stdout.read_until(b'\0', &mut record)?;
+            assert_eq!(record, text.as_bytes());
+        }
+    }
+    drop(stdin);
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "-i", "the", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(format!("{}:4\n{}:2\n", BUSTLE, FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_colors_env_and_flag() -> TestResult {
+    let expected = format!(
+        "{}\x1b[36m\x1b[K:\x1b[m\x1b[K\x1b[33m\x1b[K2\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+         The \x1b[01;32m\x1b[Kmorning\x1b[m\x1b[K after death\n",
+        BUSTLE
+    );
+    Command::cargo_bin(PRG)?
+        .env("GREP_COLORS", "ms=01;34:fn=:ln=33:bogus")
+        .args(["--color=always", "-H", "-n", "--colors", "ms=01;32", "morning", BUSTLE])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn colors_invalid() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--colors", "ms=red", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid color spec \"ms=red\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+fn sorted_lines(output: &[u8]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut lines: Vec<_> = std::str::from_utf8(output)?
+        .lines()
+        .map(|line| line.replace('\\', "/"))
+        .collect();
+    lines.sort();
+    Ok(lines)
+}
+
+#[test]
+fn recursive_respects_gitignore() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_respects_gitignore");
+    let _ = fs::remove_dir_all(&dir);
+    for sub in [".git", "build", "sub"] {
+        fs::create_dir_all(dir.join(sub))?;
+    }
+    fs::write(dir.join(".gitignore"), "*.log\n!keep.log\nbuild/\n")?;
+    fs::write(dir.join("sub/.gitignore"), "secret.txt\n")?;
+    for file in ["a.txt", "b.log", "keep.log", "build/c.txt", "sub/secret.txt", "sub/d.txt"] {
+        fs::write(dir.join(file), "foo\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+
+    let output = Command::cargo_bin(PRG)?.args(["-rl", "foo", &root]).output()?;
+    assert_eq!(
+        sorted_lines(&output.stdout)?,
+        [
+            format!("{}/a.txt", root),
+            format!("{}/keep.log", root),
+            format!("{}/sub/d.txt", root),
+        ]
+    );
+
+    // A file named outright is searched regardless
+    let secret = format!("{}/sub/secret.txt", root);
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "foo", &secret])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", secret));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_respects_ignore_files() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_respects_ignore_files");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join(".ignore"), "*.tmp\n")?;
+    fs::write(dir.join(".rgignore"), "x.txt\n")?;
+    fs::write(dir.join("sub/.greprignore"), "y.txt\n")?;
+    for file in ["a.tmp", "x.txt", "sub/x.txt", "sub/y.txt", "sub/z.txt"] {
+        fs::write(dir.join(file), "foo\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+
+    // No git repository is needed for these
+    let output = Command::cargo_bin(PRG)?.args(["-rl", "foo", &root]).output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [format!("{}/sub/z.txt", root)]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_ignore_flags() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_ignore_flags");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join(".git"))?;
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join(".gitignore"), "a.txt\nc.txt\n")?;
+    fs::write(dir.join(".ignore"), "b.txt\n")?;
+    for file in ["a.txt", "b.txt", "sub/c.txt", "sub/d.txt"] {
+        fs::write(dir.join(file), "foo\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+    let sub = format!("{}/sub", root);
+
+    for (flag, path, expected) in [
+        (None, &root, &["sub/d.txt"][..]),
+        (Some("--no-ignore-vcs"), &root, &["a.txt", "sub/c.txt", "sub/d.txt"]),
+        (Some("--no-ignore"), &root, &["a.txt", "b.txt", "sub/c.txt", "sub/d.txt"]),
+        (None, &sub, &["sub/d.txt"]),
+        (Some("--no-ignore-parent"), &sub, &["sub/c.txt", "sub/d.txt"]),
+    ] {
+        let output = Command::cargo_bin(PRG)?
+            .args(flag)
+            .args(["-rl", "foo", path])
+            .output()?;
+        let expected: Vec<_> = expected.iter().map(|file| format!("{}/{}", root, file)).collect();
+        assert_eq!(sorted_lines(&output.stdout)?, expected, "{:?}", flag);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_hidden() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_hidden");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join(".dir"))?;
+    for file in [".dir/a.txt", ".b.swp", "c.txt"] {
+        fs::write(dir.join(file), "foo\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+
+    let output = Command::cargo_bin(PRG)?.args(["-rl", "foo", &root]).output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [format!("{}/c.txt", root)]);
+
+    let output = Command::cargo_bin(PRG)?.args(["-rl", "--hidden", "foo", &root]).output()?;
+    assert_eq!(
+        sorted_lines(&output.stdout)?,
+        [
+            format!("{}/.b.swp", root),
+            format!("{}/.dir/a.txt", root),
+            format!("{}/c.txt", root),
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_file_types() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_file_types");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src"))?;
+    for file in ["src/main.rs", "build.py", "README.md"] {
+        fs::write(dir.join(file), "unsafe\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+
+    let output = Command::cargo_bin(PRG)?.args(["-rl", "-t", "rust", "unsafe", &root]).output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [format!("{}/src/main.rs", root)]);
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rl", "--type-not", "rust", "--type-not=md", "unsafe", &root])
+        .output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [format!("{}/build.py", root)]);
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-t", "nosuchtype", "unsafe", &root])
+        .assert()
+        .code(2)
+        .stderr("Unknown file type \"nosuchtype\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn type_add_and_list() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--type-add", "web:*.html,*.css", "--type-list"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\nrust: *.rs\n"))
+        .stdout(predicates::str::contains("\nweb: *.css, *.html\n"));
+
+    // Definitions kept in GREPR_OPTS add to those on the command line
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTS", "--type-add 'web:*.html'")
+        .args(["--type-add", "web:*.css", "--type-add", "tmpl:*.hbs", "--type-list"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\ntmpl: *.hbs\n"))
+        .stdout(predicates::str::contains("\nweb: *.css, *.html\n"));
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("type_add_and_list");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    for file in ["index.html", "site.css", "app.js"] {
+        fs::write(dir.join(file), "body\n")?;
+    }
+    let root = dir.to_string_lossy().replace('\\', "/");
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rl", "--type-add=web:*.html,*.css", "-t", "web", "body", &root])
+        .output()?;
+    assert_eq!(
+        sorted_lines(&output.stdout)?,
+        [format!("{}/index.html", root), format!("{}/site.css", root)]
+    );
+
+    Command::cargo_bin(PRG)?
+        .args(["--type-add", "web", "--type-list"])
+        .assert()
+        .code(2)
+        .stderr("Invalid type definition \"web\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn list_candidate_files() -> TestResult {
+    // Every positional is a path, and nothing is searched
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files", "-t", "txt", INPUTS_DIR, "-r"])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout)?,
+        [
+            "tests/inputs/bustle.txt",
+            "tests/inputs/empty.txt",
+            "tests/inputs/fox.txt",
+            "tests/inputs/nobody.txt",
+        ]
+    );
+
+    Command::cargo_bin(PRG)?
+        .args(["--files", "-r", "--exclude=*", INPUTS_DIR])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_max_depth() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files", "-r", "--max-depth", "1", "tests"])
+        .output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, ["tests/cli.rs"]);
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files", "-r", "--max-depth=2", "-t", "txt", "--exclude-dir=expected", "tests"])
+        .output()?;
+    assert_eq!(sorted_lines(&output.stdout)?.len(), 6);
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "x", "foo", "tests"])
+        .assert()
+        .code(2)
+        .stderr("Invalid max depth \"x\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_max_filesize() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursive_max_filesize");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("small.txt"), "foo\n")?;
+    fs::write(dir.join("big.txt"), "foo\n".repeat(1024))?;
+    let root = dir.to_string_lossy().replace('\\', "/");
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rl", "--max-filesize", "1K", "foo", &root])
+        .output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [format!("{}/small.txt", root)]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn smart_case() -> TestResult {
+    // All lowercase searches without regard to case
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-c", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3\n");
+
+    // Any uppercase letter makes the search exact again, unless -i says otherwise
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-c", "THE", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("0\n");
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-i", "-c", "THE", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-n", r"bustle in a house\nThe", BUSTLE])
+        .assert()
+        .success()
+        .stdout("1:The bustle in a house\n2:The morning after death\n");
+
+    // Without -U no line can contain the newline
+    Command::cargo_bin(PRG)?
+        .args([r"house\nThe", BUSTLE])
+        .assert()
+        .code(1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "[$1]", r"(\w+) fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick [brown] jumps over the lazy dog.\n");
+
+    // Named groups, and only the rewritten matches with -o
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--replace=${adj}!", r"(?P<adj>\w+) (fox|dog)", FOX])
+        .assert()
+        .success()
+        .stdout("brown!\nlazy!\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn vimgrep() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--vimgrep", "-A", "1", "-i", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{0}:1:1:The bustle in a house\n\
+             {0}:2:1:The morning after death\n\
+             {0}:6:1:The sweeping up the heart,\n\
+             {0}:6:17:The sweeping up the heart,\n",
+            BUSTLE
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn column() -> TestResult {
+    // Only selected lines get a column, that of their first match
+    Command::cargo_bin(PRG)?
+        .args(["--column", "-n", "-B", "1", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("5-\n6:17:The sweeping up the heart,\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--column", "-o", "o", FOX])
+        .assert()
+        .success()
+        .stdout("13:o\n18:o\n27:o\n42:o\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn heading() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--heading", "-n", "fox", FOX, BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{0}\n1:The quick brown fox jumps over the lazy dog.\n\n\
+             {0}\n1:The quick brown fox jumps over the lazy dog.\n",
+            FOX
+        ));
+
+    // Piped output keeps GNU's layout unless asked otherwise
+    Command::cargo_bin(PRG)?
+        .args(["fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(format!("{}:The quick brown fox jumps over the lazy dog.\n", FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_lines() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "-B", "1", "the", BUSTLE, FOX])
+        .output()?;
+    assert!(output.status.success());
+    let events: Vec<serde_json::Value> = std::str::from_utf8(&output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let types: Vec<_> = events.iter().map(|event| event["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        types,
+        ["begin", "context", "match", "end", "begin", "match", "end", "summary"]
+    );
+    assert_eq!(events[0]["data"]["path"]["text"], BUSTLE);
+    assert_eq!(events[2]["data"]["line_number"], 6);
+    assert_eq!(
+        events[2]["data"]["submatches"],
+        serde_json::json!([{ "match": { "text": "the" }, "start": 16, "end": 19 }])
+    );
+    assert_eq!(events[7]["data"]["stats"]["searches_with_match"], 2);
+    assert_eq!(events[7]["data"]["stats"]["matched_lines"], 2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats_summary() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--stats", "the", BUSTLE, FOX, EMPTY])
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let (results, summary) = stdout.split_once("\n\n").unwrap();
+    assert_eq!(
+        results,
+        format!(
+            "{}:The sweeping up the heart,\n{}:The quick brown fox jumps over the lazy dog.",
+            BUSTLE, FOX
+        )
+    );
+    let lines: Vec<_> = summary.lines().collect();
+    assert_eq!(
+        lines[..5],
+        [
+            "2 matches",
+            "2 matched lines",
+            "2 files contained matches",
+            "3 files searched",
+            "238 bytes searched",
+        ]
+    );
+    assert!(lines[5].ends_with(" seconds"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sort_results() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "--sort", "path", "-r", "", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(format!("{}:9\n{}:0\n{}:1\n{}:9\n", BUSTLE, EMPTY, FOX, NOBODY));
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", "--sortr", "size", "-r", "", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(format!("{}:9\n{}:9\n{}:1\n{}:0\n", NOBODY, BUSTLE, FOX, EMPTY));
+
+    // The last of --sort and --sortr wins
+    Command::cargo_bin(PRG)?
+        .args(["-l", "--sortr", "path", "--sort", "path", "-r", "the", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(format!("{}\n{}\n{}\n", BUSTLE, FOX, NOBODY));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_keep_file_order() -> TestResult {
+    let args = ["-C", "1", "--stats", "the", BUSTLE, EMPTY, FOX, NOBODY];
+    let serial = Command::cargo_bin(PRG)?.arg("-j1").args(args).output()?;
+    let parallel = Command::cargo_bin(PRG)?.arg("--threads=4").args(args).output()?;
+    assert!(parallel.status.success());
+    // Only the elapsed time on the last line may differ
+    let without_time = |stdout: &[u8]| {
+        let stdout = String::from_utf8_lossy(stdout).into_owned();
+        stdout[..stdout.trim_end().rfind('\n').unwrap()].to_string()
+    };
+    assert_eq!(without_time(&serial.stdout), without_time(&parallel.stdout));
+    assert!(without_time(&parallel.stdout).starts_with(&format!("{}-\n{}:", BUSTLE, BUSTLE)));
+
+    Command::cargo_bin(PRG)?
+        .args(["-j", "many", "the", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid thread count \"many\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_stream_large_output() -> TestResult {
+    // Files printing more than a worker holds are streamed once their turn
+    // comes, which must look no different from a search on one thread
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("threads_stream_large_output");
+    fs::create_dir_all(&dir)?;
+    let lines: String = (0..20_000)
+        .map(|i| if i % 4 == 0 { format!("hit {}\n", i) } else { format!("miss {}\n", i) })
+        .collect();
+    fs::write(dir.join("a.txt"), "nothing here\n")?;
+    fs::write(dir.join("b.txt"), &lines)?;
+    fs::write(dir.join("c.txt"), "hit once\n")?;
+    fs::write(dir.join("d.txt"), &lines)?;
+    let dir = dir.to_string_lossy().into_owned();
+
+    for extra in [&["-A", "1"][..], &["-B", "1", "--heading"], &["-n", "--color=always"]] {
+        let args = [&["-r", "--sort", "path"], extra, &["hit", &dir]].concat();
+        let serial = Command::cargo_bin(PRG)?.arg("-j1").args(&args).output()?;
+        let parallel = Command::cargo_bin(PRG)?.arg("-j4").args(&args).output()?;
+        assert!(parallel.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&serial.stdout),
+            String::from_utf8_lossy(&parallel.stdout)
+        );
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn mmap_matches_buffered_reads() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("mmap_matches_buffered_reads");
+    fs::create_dir_all(&dir)?;
+    // Large enough to be mapped even without --mmap
+    let big = dir.join("big.txt");
+    fs::write(&big, "filler line\n".repeat(100_000) + "needle at the end\n")?;
+    let big = big.to_string_lossy().into_owned();
+
+    for flags in [&["--mmap"][..], &["--no-mmap"], &[]] {
+        Command::cargo_bin(PRG)?
+            .args(flags)
+            .args(["-n", "needle", &big])
+            .assert()
+            .success()
+            .stdout("100001:needle at the end\n");
+    }
+
+    // Standard input can never be mapped, so it is read as usual
+    Command::cargo_bin(PRG)?
+        .args(["--mmap", "-c", "fox"])
+        .write_stdin(fs::read_to_string(FOX)?)
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn encoding_transcodes() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("encoding_transcodes");
+    fs::create_dir_all(&dir)?;
+    let utf16 = dir.join("utf16le.txt");
+    let text = "naïve café\nplain\n";
+    fs::write(&utf16, text.encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>())?;
+    let latin1 = dir.join("latin1.txt");
+    fs::write(&latin1, b"na\xefve caf\xe9\nplain\n")?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-E", "utf-16le", "café", &utf16.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout("naïve café\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--encoding=latin1", "-c", "ï", &latin1.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-E", "klingon", "café", FOX])
+        .assert()
+        .failure()
+        .stderr("Unknown encoding \"klingon\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bom_selects_encoding() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bom_selects_encoding");
+    fs::create_dir_all(&dir)?;
+    let text = "id,name\n1,café\n";
+    let mut utf16le = vec![0xFF, 0xFE];
+    utf16le.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    let mut utf16be = vec![0xFE, 0xFF];
+    utf16be.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    let mut utf8 = b"\xEF\xBB\xBF".to_vec();
+    utf8.extend(text.as_bytes());
+    for (name, bytes) in [("le.csv", utf16le), ("be.csv", utf16be), ("utf8.csv", utf8)] {
+        fs::write(dir.join(name), bytes)?;
+        let path = dir.join(name).to_string_lossy().into_owned();
+
+        // The mark itself is gone, so anchors see the first line as written
+        Command::cargo_bin(PRG)?
+            .args(["-e", "^id", "-e", "café", &path])
+            .assert()
+            .success()
+            .stdout(text);
+
+        Command::cargo_bin(PRG)?
+            .args(["-E", "none", "-q", "^id", &path])
+            .assert()
+            .failure();
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(all(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd"))]
+fn search_zip_decompresses() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("search_zip_decompresses");
+    fs::create_dir_all(&dir)?;
+    // fox.txt in each format
+    let archives: [(&str, &[u8]); 4] = [
+        ("gz", b"\x1F\x8B\x08\x00\x00\x00\x00\x00\x02\xFF\x01-\x00\xD2\xFFThe quick brown fox jumps over the lazy dog.\x0Aj\xCCP\xEB-\x00\x00\x00"),
+        ("bz2", b"BZh91AY&SY\xB35\xB6\xF2\x00\x00\x05S\x80\x00\x10@\x01\x04\x00?\xFF\xFF\xF0 \x00#\x1E$\x1A\x0D\x18\x86\xD1\xB5!@\x00\x00\x00\x0E\x02C\x85%\xED\xFEo2\x9E\x0Ct\xB4Y\xDE\x91\x8A$\x16\xC9\x1A\xB2\x01\xD67\xBF\xE2\xEEH\xA7\x0A\x12\x16f\xB6\xDE@"),
+        ("xz", b"\xFD7zXZ\x00\x00\x04\xE6\xD6\xB4F\x02\x00!\x01\x1C\x00\x00\x00\x10\xCFX\xCC\x01\x00,The quick brown fox jumps over the lazy dog.\x0A\x00\x00\x00\x00\xE3&\x0BR\x04Y\xD2(\x00\x01E-\x1ERW\xE8\x1F\xB6\xF3}\x01\x00\x00\x00\x00\x04YZ"),
+        ("zst", b"(\xB5/\xFD\x00hi\x01\x00The quick brown fox jumps over the lazy dog.\x0A"),
+    ];
+    let expected = fs::read_to_string(FOX)?;
+    for (ext, bytes) in archives {
+        // One named for its format, one recognised by its magic bytes alone
+        for name in [format!("fox.txt.{}", ext), format!("fox-{}", ext)] {
+            let path = dir.join(name);
+            fs::write(&path, bytes)?;
+            Command::cargo_bin(PRG)?
+                .args(["--search-zip", "lazy", &path.to_string_lossy()])
+                .assert()
+                .success()
+                .stdout(expected.clone());
+        }
+    }
+
+    // Without the flag the archive is just a binary file
+    let gz = dir.join("fox.txt.gz").to_string_lossy().into_owned();
+    Command::cargo_bin(PRG)?
+        .args(["lazy", &gz])
+        .assert()
+        .success()
+        .stdout(format!("Binary file {} matches\n", gz));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pre_searches_command_output() -> TestResult {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pre_searches_command_output");
+    fs::create_dir_all(&dir)?;
+    let upper = dir.join("upper.sh");
+    fs::write(&upper, "#!/bin/sh\ntr a-z A-Z < \"$1\"\n")?;
+    let failing = dir.join("failing.sh");
+    fs::write(&failing, "#!/bin/sh\necho \"cannot convert $1\" >&2\nexit 3\n")?;
+    for script in [&upper, &failing] {
+        fs::set_permissions(script, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--pre", &upper.to_string_lossy(), "FOX", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(format!("{}:THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG.\n", FOX));
+
+    Command::cargo_bin(PRG)?
+        .args(["--pre", &failing.to_string_lossy(), "fox", FOX])
+        .assert()
+        .failure()
+        .stderr(format!(
+            "{}: preprocessor \"{}\" failed: cannot convert {}\n",
+            FOX,
+            failing.display(),
+            FOX
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pre_glob_limits_preprocessing() -> TestResult {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pre_glob_limits_preprocessing");
+    fs::create_dir_all(&dir)?;
+    let upper = dir.join("upper.sh");
+    fs::write(&upper, "#!/bin/sh\ntr a-z A-Z < \"$1\"\n")?;
+    fs::set_permissions(&upper, fs::Permissions::from_mode(0o755))?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--pre", &upper.to_string_lossy(), "--pre-glob", "f*.txt", "-i", "the (quick|sweeping)", FOX, BUSTLE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}:THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG.\n{}:The sweeping up the heart,\n",
+            FOX, BUSTLE
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn glob_filters_paths() -> TestResult {
+    // Globs with a slash match the whole path, later ones taking precedence
+    let output = Command::cargo_bin(PRG)?
+        .args(["-rl", "-g", "tests/inputs/*.txt", "-g", "!n*", "the", "tests"])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output.stdout)?, [BUSTLE, FOX]);
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files", "-r", "--iglob", "**/INPUTS/F*.TXT", INPUTS_DIR])
+        .output()?;
+    assert_eq!(sorted_lines(&output.stdout)?, [FOX]);
+
+    // A file named outright is searched whatever the globs say
+    Command::cargo_bin(PRG)?
+        .args(["-g", "!*.txt", "-c", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-g", "a{b", "fox", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr("Invalid glob \"a{b\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn passthru_prints_every_line() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--passthru", "-n", "Nobody", NOBODY])
+        .assert()
+        .success()
+        .stdout(
+            fs::read_to_string(NOBODY)?
+                .split_inclusive('\n')
+                .enumerate()
+                .map(|(i, line)| {
+                    let sep = if line.contains("Nobody") { ':' } else { '-' };
+                    format!("{}{}{}", i + 1, sep, line)
+                })
+                .collect::<String>(),
+        );
+
+    Command::cargo_bin(PRG)?
+        .args(["--passthrough", "--color=always", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown \x1b[01;31m\x1b[Kfox\x1b[m\x1b[K jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_unicode_matches_ascii_only() -> TestResult {
+    // The dash is one character to Unicode classes but three bytes without
+    Command::cargo_bin(PRG)?
+        .args(["-c", r"you\W\W\WNobody", NOBODY])
+        .assert()
+        .failure()
+        .stdout("0\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--no-unicode", "-c", r"you\W\W\WNobody", NOBODY])
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--no-unicode", "-P", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("--no-unicode is not supported by the -P/--pcre2 engine\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn trim_is_opt_in() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("trim_is_opt_in");
+    fs::create_dir_all(&dir)?;
+    let file = dir.join("indented.txt");
+    fs::write(&file, "\t  let fox = 1;  \nfox\n")?;
+    let file = file.to_string_lossy().into_owned();
+
+    // Lines come out exactly as they are in the file
+    Command::cargo_bin(PRG)?
+        .args(["fox", &file])
+        .assert()
+        .success()
+        .stdout("\t  let fox = 1;  \nfox\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--trim", "-b", "--color=always", "fox", &file])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "\x1b[32m\x1b[K3\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K",
+            "let \x1b[01;31m\x1b[Kfox\x1b[m\x1b[K = 1;\n",
+            "\x1b[32m\x1b[K18\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K",
+            "\x1b[01;31m\x1b[Kfox\x1b[m\x1b[K\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn one_file_system_skips_mounts() -> TestResult {
+    // /proc is its own file system, so it is only entered without the flag
+    let procfs_files = |extra: &[&str]| -> Result<usize, Box<dyn std::error::Error>> {
+        let output = Command::cargo_bin(PRG)?
+            .args(["--files", "-r", "--max-depth", "2", "-s"])
+            .args(extra)
+            .arg("/")
+            .output()?;
+        Ok(std::str::from_utf8(&output.stdout)?.lines().filter(|line| line.starts_with("/proc/")).count())
+    };
+    assert!(procfs_files(&[])? > 0);
+    assert_eq!(procfs_files(&["--one-file-system"])?, 0);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_list() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("files_from_list");
+    fs::create_dir_all(&dir)?;
+    let list = dir.join("list.txt");
+    fs::write(&list, format!("{}\n\n{}\n", FOX, BUSTLE))?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", "--files-from", &list.to_string_lossy(), "the"])
+        .assert()
+        .success()
+        .stdout(format!("{}:1\n{}:1\n", FOX, BUSTLE));
+
+    // The list can come from stdin, and named files are searched first
+    Command::cargo_bin(PRG)?
+        .args(["-l", "--files-from", "-", "the", NOBODY])
+        .write_stdin(format!("{}\n", FOX))
+        .assert()
+        .success()
+        .stdout(format!("{}\n{}\n", NOBODY, FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from_list() -> TestResult {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("files0_from_list");
+    fs::create_dir_all(&dir)?;
+    let odd = dir.join("two\nlines.txt");
+    fs::write(&odd, "the fox\n")?;
+    let odd = odd.to_string_lossy().into_owned();
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", "--files0-from", "-", "fox"])
+        .write_stdin(format!("{}\0{}\0", odd, FOX))
+        .assert()
+        .success()
+        .stdout(format!("{}:1\n{}:1\n", odd, FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hyperlink_format_links_names() -> TestResult {
+    let fox = Path::new(FOX).canonicalize()?;
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "--colors=fn=", "--hyperlink-format", "vscode", "-H", "--column", "fox", FOX])
+        .assert()
+        .success()
+        .stdout(format!(
+            "\x1b]8;;vscode://file{}:1:17\x1b\\{}\x1b]8;;\x1b\\\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             \x1b[32m\x1b[K17\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             The quick brown \x1b[01;31m\x1b[Kfox\x1b[m\x1b[K jumps over the lazy dog.\n",
+            fox.display(),
+            FOX
+        ));
+
+    // Links are left out of uncolored output, and off entirely with none
+    for format in ["default", "none"] {
+        Command::cargo_bin(PRG)?
+            .args(["-l", "--hyperlink-format", format, "fox", FOX])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", FOX));
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--hyperlink-format", "file://{file}", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid hyperlink format \"file://{file}\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_columns_elides_long_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-M", "30", "-e", "fox", "-e", "heart", FOX, BUSTLE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}:[Omitted long line with 1 matches]\n{}:The sweeping up the heart,\n",
+            FOX, BUSTLE
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-columns=19", "--max-columns-preview", "--color=always", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown \x1b[01;31m\x1b[Kfox\x1b[m\x1b[K [... 25 more bytes]\n");
+
+    // The cut never splits a character
+    Command::cargo_bin(PRG)?
+        .args(["-M", "9", "--max-columns-preview", "—", NOBODY])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Are you [... 17 more bytes]\n"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_size_limit() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--regex-size-limit", "1K", r"\w{50}", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(
+            "Pattern \"\\w{50}\" exceeds the regex size limit of 1024 bytes",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--regex-size-limit", "1M", "--dfa-size-limit", "1M", r"\w{5}", FOX])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .args(["--dfa-size-limit", "lots", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid DFA size limit \"lots\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn use_index_skips_ruled_out_files() -> TestResult {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let dir = tmp.join("use_index");
+    let cache = tmp.join("use_index_cache");
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&cache);
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join("a.txt"), "a needle here\n")?;
+    fs::write(dir.join("sub/b.txt"), "nothing\n")?;
+    let dir = dir.to_string_lossy();
+    let cache = cache.to_string_lossy();
+
+    Command::cargo_bin(PRG)?
+        .args(["index", "build", &dir, "--index-dir", &cache])
+        .assert()
+        .success()
+        .stdout(format!("Indexed 2 files under {}\n", dir));
+
+    let search = |extra: &[&str]| -> Result<String, Box<dyn std::error::Error>> {
+        let output = Command::cargo_bin(PRG)?
+            .args(["--use-index", "--index-dir", &cache, "-r", "--stats", "--sort", "path"])
+            .args(extra)
+            .arg(&*dir)
+            .output()?;
+        Ok(String::from_utf8(output.stdout)?)
+    };
+    let stdout = search(&["needle"])?;
+    assert!(stdout.starts_with(&format!("{}/a.txt:a needle here\n", dir)));
+    assert!(stdout.contains("\n1 files searched\n"));
+
+    // Regex syntax, or output for files without matches, searches everything
+    assert!(search(&["ne+dle"])?.contains("\n2 files searched\n"));
+    assert!(search(&["-c", "needle"])?.contains(&format!("{}/sub/b.txt:0\n", dir)));
+
+    // A file changed since the index was built is searched again
+    fs::OpenOptions::new()
+        .append(true)
+        .open(format!("{}/sub/b.txt", dir))?
+        .write_all(b"needle too\n")?;
+    let stdout = search(&["needle"])?;
+    assert!(stdout.contains(&format!("{}/sub/b.txt:needle too\n", dir)));
+    assert!(stdout.contains("\n2 files searched\n"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grepr_opts() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTS", "-i --line-number")
+        .args(["FOX", FOX])
+        .assert()
+        .success()
+        .stdout("1:The quick brown fox jumps over the lazy dog.\n");
+
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTS", "--invert-match")
+        .args(["fox", FOX])
+        .assert()
+        .code(1);
+
+    // Options given again on the command line replace the defaults
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTS", "-m 5")
+        .args(["-m", "1", "The", BUSTLE])
+        .assert()
+        .success()
+        .stdout("The bustle in a house\n");
+
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTS", "-g 'oops")
+        .args(["fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid GREPR_OPTS \"-g 'oops\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn rejects_conflicting_flags() -> TestResult {
+    for counting in ["-c", "--count-matches"] {
+        Command::cargo_bin(PRG)?
+            .args([counting, "-o", "fox", FOX])
+            .assert()
+            .code(2)
+            .stderr(predicate::str::starts_with(
+                "error: the argument '--only-matching' cannot be used with",
+            ));
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "hyperscan", "-P", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Command::cargo_bin(PRG)?
+        .args(["--line-numbr", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("tip: a similar argument exists: '--line-number'"));
+
+    // Neither "help" nor -h is taken by clap
+    Command::cargo_bin(PRG)?
+        .args(["-h", "help", "-"])
+        .write_stdin("no help here\n")
+        .assert()
+        .success()
+        .stdout("no help here\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn generate_completions() -> TestResult {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        Command::cargo_bin(PRG)?
+            .args(["--generate-completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("line-number"));
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "tcsh"])
+        .assert()
+        .code(2);
+
+    Command::cargo_bin(PRG)?
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("generate-completions").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn generate_man() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("--generate-man")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".TH grepr 1"))
+        .stdout(predicate::str::contains(r"\fB\-\-line\-number\fR"))
+        .stdout(predicate::str::contains("generate").not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn verbose_and_debug_log_to_stderr() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--verbose", "-r", "--exclude", "fox*", "The", "tests/inputs"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tests/inputs/bustle.txt:The"))
+        .stderr(predicate::str::contains(
+            "skipping tests/inputs/fox.txt: excluded by --include or --exclude",
+        ))
+        .stderr(predicate::str::contains("search finished"))
+        .stderr(predicate::str::contains("time.busy").not());
+
+    Command::cargo_bin(PRG)?
+        .args(["--debug", "dog", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n")
+        .stderr(predicate::str::contains("matching with regex"))
+        .stderr(predicate::str::contains("searched tests/inputs/fox.txt matched_lines=1"))
+        .stderr(predicate::str::contains("time.busy="));
+
+    // Nothing is logged unless asked for
+    Command::cargo_bin(PRG)?
+        .args(["dog", FOX])
+        .assert()
+        .success()
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bench_reports_each_run() -> TestResult {
+    let run = r"[\d.]+ ms, [\d.]+ MB/s \(walk [\d.]+ ms, read [\d.]+ ms, match [\d.]+ ms\)";
+    let expected = format!("^iteration 1: {run}\niteration 2: {run}\nmean of 2: {run}\n$");
+    Command::cargo_bin(PRG)?
+        .args(["--bench", "2", "The", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(expected)?);
+
+    Command::cargo_bin(PRG)?
+        .args(["--bench", "0", "The", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr("Invalid iteration count \"0\"\n");
+
+    // Every run is timed, but the exit status is the first's
+    Command::cargo_bin(PRG)?
+        .args(["--bench", "2", "dog", BUSTLE])
+        .assert()
+        .code(1);
+
+    Command::cargo_bin(PRG)?
+        .args(["--bench", "2", "The"])
+        .write_stdin("The fox\n")
+        .assert()
+        .code(2)
+        .stderr("--bench is not supported when reading standard input\n");
+    Ok(())
+}